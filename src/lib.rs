@@ -5,3 +5,12 @@ pub use server::request;
 pub use server::response;
 pub use server::http_enums;
 pub use server::server_errors;
+pub use server::router;
+pub use server::middleware;
+pub use server::request_reader;
+pub use server::multipart;
+pub use server::connection;
+pub use server::header_map;
+pub use server::cookie;
+pub use server::shutdown;
+pub use server::session;
@@ -3,13 +3,16 @@ extern crate serde_json;
 
 use rusttp::tcp_server::TCPServer;
 use rusttp::http_enums::ResponseStatusCode;
-use std::thread::sleep;
-use std::time::Duration;
+use rusttp::router::Router;
 
 fn main() {
-    let server = TCPServer::new("7878", 4, 8);
+    let server = TCPServer::new("127.0.0.1:7878", 4, 8).unwrap();
 
-    server.listen(|_req, mut res| {
-        res.send(ResponseStatusCode::OK);
+    let mut router = Router::new();
+    router.get("/", |_req, res| {
+        res.send(ResponseStatusCode::OK)?;
+        Ok(())
     });
+
+    server.serve(router);
 }
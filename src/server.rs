@@ -3,3 +3,14 @@ pub mod request;
 pub mod response;
 pub mod http_enums;
 pub mod server_errors;
+pub mod router;
+pub mod middleware;
+pub mod request_reader;
+pub mod multipart;
+pub mod connection;
+pub mod header_map;
+pub mod cookie;
+pub mod shutdown;
+pub mod session;
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,288 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(Option<PathBuf>),
+    Unknown,
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            PeerAddr::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            PeerAddr::Unix(None) => write!(f, "unix:unnamed"),
+            PeerAddr::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl Connection {
+    pub fn try_clone(&self) -> io::Result<Connection> {
+        match self {
+            Connection::Tcp(stream) => stream.try_clone().map(Connection::Tcp),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.try_clone().map(Connection::Unix),
+        }
+    }
+
+    pub fn peer_addr(&self) -> PeerAddr {
+        match self {
+            Connection::Tcp(stream) => match stream.peer_addr() {
+                Ok(address) => PeerAddr::Tcp(address),
+                Err(_) => PeerAddr::Unknown
+            },
+            #[cfg(unix)]
+            Connection::Unix(stream) => match stream.peer_addr() {
+                Ok(address) => PeerAddr::Unix(address.as_pathname().map(Path::to_path_buf)),
+                Err(_) => PeerAddr::Unknown
+            },
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(how),
+        }
+    }
+}
+
+impl From<TcpStream> for Connection {
+    fn from(stream: TcpStream) -> Connection {
+        Connection::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Connection {
+    fn from(stream: UnixStream) -> Connection {
+        Connection::Unix(stream)
+    }
+}
+
+impl Read for &Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for &Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => (&*stream).flush(),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub(crate) enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    pub(crate) fn wake(&self) {
+        match self {
+            ListenAddr::Tcp(address) => {
+                let _ = TcpStream::connect_timeout(address, Duration::from_secs(1));
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                let _ = UnixStream::connect(path);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug,PartialEq,Clone)]
+pub struct UnixSocketOptions {
+    pub permissions: Option<u32>,
+    pub remove_existing: bool,
+}
+
+#[cfg(unix)]
+impl Default for UnixSocketOptions {
+    fn default() -> UnixSocketOptions {
+        UnixSocketOptions { permissions: None, remove_existing: true }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    pub(crate) fn bind<P: AsRef<Path>>(path: P, options: &UnixSocketOptions) -> io::Result<UnixSocket> {
+        let path = path.as_ref().to_path_buf();
+
+        if options.remove_existing {
+            if let Ok(metadata) = fs::symlink_metadata(&path) {
+                if !metadata.file_type().is_socket() {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                              format!("{} exists and is not a socket", path.display())));
+                }
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        let socket = UnixSocket { listener, path };
+
+        if let Some(mode) = options.permissions {
+            fs::set_permissions(&socket.path, fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(socket)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+impl Listener {
+    pub(crate) fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Connection::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(socket) => socket.listener.accept().map(|(stream, _)| Connection::Unix(stream)),
+        }
+    }
+
+    pub(crate) fn listen_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(socket) => Ok(ListenAddr::Unix(socket.path.clone())),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rusttp-{}-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn unix_socket_cleanup_and_permissions() {
+        let path = socket_path("cleanup");
+        let options = UnixSocketOptions { permissions: Some(0o660), remove_existing: true };
+
+        let socket = UnixSocket::bind(&path, &options).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let client = UnixStream::connect(&path).unwrap();
+        let server = Listener::Unix(socket);
+        let connection = server.accept().unwrap();
+        assert_eq!(connection.peer_addr(), PeerAddr::Unix(None));
+        assert_eq!(connection.peer_addr().to_string(), "unix:unnamed");
+        drop(client);
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unix_socket_refuses_to_replace_regular_file() {
+        let path = socket_path("regular");
+        fs::write(&path, b"not a socket").unwrap();
+
+        let err = UnixSocket::bind(&path, &UnixSocketOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
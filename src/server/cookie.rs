@@ -0,0 +1,328 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use crate::header_map::is_token_byte;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNATURE_SEPARATOR: char = '.';
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SameSite::Strict => f.write_str("Strict"),
+            SameSite::Lax => f.write_str("Lax"),
+            SameSite::None => f.write_str("None")
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: String::from(name),
+            value: String::from(value),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").max_age(Duration::from_secs(0)).expires(UNIX_EPOCH)
+    }
+
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(String::from(path));
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(String::from(domain));
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn signed(mut self, key: &CookieKey) -> Cookie {
+        self.value = key.sign(&self.name, &self.value);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let value = self.value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(&self.value);
+        !self.name.is_empty() && self.name.bytes().all(is_token_byte)
+            && value.bytes().all(is_cookie_octet)
+            && self.path.iter().chain(self.domain.iter()).all(|attribute| attribute.bytes().all(is_attribute_octet))
+    }
+}
+
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+fn is_attribute_octet(byte: u8) -> bool {
+    !byte.is_ascii_control() && byte != b';'
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    pub fn parse<'a, I: IntoIterator<Item = &'a str>>(headers: I) -> CookieJar {
+        let mut cookies: Vec<(String, String)> = Vec::new();
+        for header in headers {
+            for pair in header.split(';') {
+                let (name, value) = match pair.split_once('=') {
+                    Some((name, value)) => (name.trim(), value.trim()),
+                    None => continue
+                };
+                if name.is_empty() {
+                    continue;
+                }
+                let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+                cookies.push((String::from(name), String::from(value)));
+            }
+        }
+        CookieJar { cookies }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_signed(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.verify(name, self.get(name)?)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[derive(Clone)]
+pub struct CookieKey {
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CookieKey { .. }")
+    }
+}
+
+impl CookieKey {
+    pub fn new(secret: &[u8]) -> CookieKey {
+        CookieKey { secret: secret.to_vec() }
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let signature = self.mac(name, value).finalize().into_bytes();
+        format!("{}{}{}", URL_SAFE_NO_PAD.encode(signature), SIGNATURE_SEPARATOR, value)
+    }
+
+    pub fn verify(&self, name: &str, signed_value: &str) -> Option<String> {
+        let (signature, value) = signed_value.split_once(SIGNATURE_SEPARATOR)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        match self.mac(name, value).verify_slice(&signature) {
+            Ok(()) => Some(String::from(value)),
+            Err(_) => None
+        }
+    }
+}
+
+fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = seconds / 86400;
+    let (hour, minute, second) = (seconds % 86400 / 3600, seconds % 3600 / 60, seconds % 60);
+
+    let z = days as i64 + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT", DAYS[(days % 7) as usize], day, MONTHS[(month - 1) as usize],
+            year, hour, minute, second)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_set_cookie() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_secs(3600))
+            .expires(UNIX_EPOCH + Duration::from_secs(1445412480))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        assert_eq!(cookie.to_string(), "id=a3fWa; Path=/; Domain=example.com; Max-Age=3600; \
+                                        Expires=Wed, 21 Oct 2015 07:28:00 GMT; Secure; HttpOnly; SameSite=Lax");
+        assert_eq!(Cookie::removal("id").path("/").to_string(),
+                   "id=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn validate_cookies() {
+        assert!(Cookie::new("pref", "dark").path("/").domain("example.com").is_valid());
+        assert!(Cookie::new("pref", "\"quoted\"").is_valid());
+        assert!(Cookie::removal("pref").is_valid());
+        assert!(Cookie::new("user", "42").signed(&CookieKey::new(b"secret")).is_valid());
+
+        assert!(!Cookie::new("pref", "x; Domain=evil.com").is_valid());
+        assert!(!Cookie::new("pref", "a b").is_valid());
+        assert!(!Cookie::new("pref", "a\r\nSet-Cookie: evil=1").is_valid());
+        assert!(!Cookie::new("bad name", "x").is_valid());
+        assert!(!Cookie::new("", "x").is_valid());
+        assert!(!Cookie::new("pref", "x").path("/; Domain=evil.com").is_valid());
+        assert!(!Cookie::new("pref", "x").domain("example.com\r\nX: y").is_valid());
+    }
+
+    #[test]
+    fn parse_cookie_jar() {
+        let jar = CookieJar::parse(vec!["theme=dark; session=\"abc\"", "lang=en;broken; =x"]);
+        assert_eq!(jar.len(), 3);
+        assert_eq!(jar.get("theme"), Some("dark"));
+        assert_eq!(jar.get("session"), Some("abc"));
+        assert_eq!(jar.get("lang"), Some("en"));
+        assert!(!jar.contains("broken"));
+    }
+
+    #[test]
+    fn signed_cookies() {
+        let key = CookieKey::new(b"server secret");
+        let cookie = Cookie::new("user", "42").signed(&key);
+        assert_ne!(cookie.value(), "42");
+
+        let header = format!("user={}", cookie.value());
+        let jar = CookieJar::parse(vec![header.as_str()]);
+        assert_eq!(jar.get_signed("user", &key), Some(String::from("42")));
+        assert_eq!(jar.get_signed("user", &CookieKey::new(b"other secret")), None);
+
+        let tampered = header.replace(".42", ".43");
+        let jar = CookieJar::parse(vec![tampered.as_str()]);
+        assert_eq!(jar.get_signed("user", &key), None);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap { entries: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.entries.iter().any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries.iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        match self.entries.iter().position(|(key, _)| key.eq_ignore_ascii_case(name)) {
+            Some(index) => {
+                self.entries[index] = (String::from(name), String::from(value));
+                let mut position = 0;
+                self.entries.retain(|(key, _)| {
+                    position += 1;
+                    position - 1 <= index || !key.eq_ignore_ascii_case(name)
+                });
+            }
+            None => self.append(name, value)
+        }
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((String::from(name), String::from(value)));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Vec<String> {
+        let mut removed: Vec<String> = Vec::new();
+        self.entries.retain(|(key, value)| {
+            if key.eq_ignore_ascii_case(name) {
+                removed.push(value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (key, _) in self.entries.iter() {
+            if !names.iter().any(|name| name.eq_ignore_ascii_case(key)) {
+                names.push(key);
+            }
+        }
+        names
+    }
+
+    pub(crate) fn parse_line(line: &str) -> Option<(&str, &str)> {
+        let (name, value) = line.split_once(':')?;
+        if !is_valid_name(name) {
+            return None;
+        }
+        Some((name, value.trim_matches(|c| c == ' ' || c == '\t')))
+    }
+
+    pub(crate) fn invalid_entry(&self) -> Option<&str> {
+        self.entries.iter()
+            .find(|(key, value)| !is_valid_name(key) || !is_valid_value(value))
+            .map(|(key, _)| key.as_str())
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(is_token_byte)
+}
+
+pub fn is_valid_value(value: &str) -> bool {
+    !value.bytes().any(|byte| byte == b'\r' || byte == b'\n' || byte == 0)
+}
+
+pub(crate) fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+impl fmt::Display for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in self.entries.iter() {
+            write!(f, "{}: {}\r\n", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a str)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for HeaderMap {
+    fn from_iter<T: IntoIterator<Item = (N, V)>>(iter: T) -> HeaderMap {
+        HeaderMap { entries: iter.into_iter().map(|(key, value)| (key.into(), value.into())).collect() }
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> Extend<(N, V)> for HeaderMap {
+    fn extend<T: IntoIterator<Item = (N, V)>>(&mut self, iter: T) {
+        self.entries.extend(iter.into_iter().map(|(key, value)| (key.into(), value.into())));
+    }
+}
+
+impl From<HashMap<String, String>> for HeaderMap {
+    fn from(headers: HashMap<String, String>) -> HeaderMap {
+        headers.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_multi_valued() {
+        let mut headers = HeaderMap::new();
+        headers.append("Accept", "text/html");
+        headers.append("Content-Type", "application/json");
+        headers.append("accept", "application/json");
+
+        assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+        assert_eq!(headers.get_all("Accept"), vec!["text/html", "application/json"]);
+        assert_eq!(headers.get("content-type"), Some("application/json"));
+        assert!(headers.contains_key("CONTENT-TYPE"));
+        assert_eq!(headers.names(), vec!["Accept", "Content-Type"]);
+
+        headers.insert("ACCEPT", "*/*");
+        let entries: Vec<(&str, &str)> = headers.iter().collect();
+        assert_eq!(entries, vec![("ACCEPT", "*/*"), ("Content-Type", "application/json")]);
+
+        assert_eq!(headers.remove("content-type"), vec![String::from("application/json")]);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.to_string(), "ACCEPT: */*\r\n");
+    }
+
+    #[test]
+    fn find_invalid_entries() {
+        let mut headers = HeaderMap::new();
+        headers.append("X-Echo", "plain value");
+        assert_eq!(headers.invalid_entry(), None);
+
+        headers.append("X-Inject", "a\r\nSet-Cookie: evil=1");
+        assert_eq!(headers.invalid_entry(), Some("X-Inject"));
+
+        let headers: HeaderMap = vec![("Bad Name", "x")].into_iter().collect();
+        assert_eq!(headers.invalid_entry(), Some("Bad Name"));
+        assert!(!is_valid_value("nul\0byte"));
+    }
+
+    #[test]
+    fn parse_header_lines() {
+        assert_eq!(HeaderMap::parse_line("Foo:bar"), Some(("Foo", "bar")));
+        assert_eq!(HeaderMap::parse_line("Foo: \t bar baz \t"), Some(("Foo", "bar baz")));
+        assert_eq!(HeaderMap::parse_line("Empty:"), Some(("Empty", "")));
+        assert_eq!(HeaderMap::parse_line("Time: 12:30"), Some(("Time", "12:30")));
+        assert_eq!(HeaderMap::parse_line("Foo : bar"), None);
+        assert_eq!(HeaderMap::parse_line(" Foo: bar"), None);
+        assert_eq!(HeaderMap::parse_line(": bar"), None);
+        assert_eq!(HeaderMap::parse_line("no colon"), None);
+    }
+}
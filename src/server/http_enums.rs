@@ -1,6 +1,6 @@
 use std::fmt;
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone,Copy)]
 pub enum RequestMethod {
     CONNECT,
     DELETE,
@@ -14,6 +14,7 @@ pub enum RequestMethod {
 }
 
 impl RequestMethod {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(method: &str) -> Option<RequestMethod> {
         match method {
             "CONNECT" => Some(RequestMethod::CONNECT),
@@ -46,9 +47,42 @@ impl fmt::Display for RequestMethod {
     }
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum HttpVersion {
+    HTTP_1_0,
+    HTTP_1_1,
+}
+
+impl HttpVersion {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(version: &str) -> Option<HttpVersion> {
+        match version {
+            "HTTP/1.0" => Some(HttpVersion::HTTP_1_0),
+            "HTTP/1.1" => Some(HttpVersion::HTTP_1_1),
+            _ => None
+        }
+    }
+
+    pub fn keep_alive_by_default(&self) -> bool {
+        match self {
+            HttpVersion::HTTP_1_0 => false,
+            HttpVersion::HTTP_1_1 => true
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpVersion::HTTP_1_0 => f.write_str("HTTP/1.0"),
+            HttpVersion::HTTP_1_1 => f.write_str("HTTP/1.1")
+        }
+    }
+}
 
 #[allow(non_camel_case_types)]
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone,Copy)]
 pub enum ResponseStatusCode {
     // 10x - Information responses
     CONTINUE,
@@ -115,6 +149,13 @@ pub enum ResponseStatusCode {
     NETWORK_AUTHENTICATION_REQUIRED,
 }
 
+impl ResponseStatusCode {
+    pub fn allows_body(&self) -> bool {
+        !matches!(self, ResponseStatusCode::CONTINUE | ResponseStatusCode::SWITCHING_PROTOCOLS |
+            ResponseStatusCode::EARLY_HINTS | ResponseStatusCode::NO_CONTENT | ResponseStatusCode::NOT_MODIFIED)
+    }
+}
+
 impl fmt::Display for ResponseStatusCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
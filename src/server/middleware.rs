@@ -0,0 +1,16 @@
+use crate::request::Request;
+use crate::response::{OutgoingResponse, Response};
+
+#[derive(Debug,PartialEq)]
+pub enum MiddlewareFlow {
+    Next,
+    Stop,
+}
+
+pub trait Middleware: Send + Sync + 'static {
+    fn before(&self, _request: &mut Request, _response: &mut Response) -> MiddlewareFlow {
+        MiddlewareFlow::Next
+    }
+
+    fn after(&self, _response: &mut OutgoingResponse) {}
+}
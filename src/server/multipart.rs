@@ -0,0 +1,566 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use crate::header_map::HeaderMap;
+use crate::request::Request;
+use crate::request_reader::RequestLimits;
+use crate::server_errors::{RequestErrors, RequestResult};
+use percent_encoding::percent_decode_str;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
+const TEMP_FILE_NAME_BYTES: usize = 16;
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct MultipartLimits {
+    pub max_part_size: usize,
+    pub max_total_size: usize,
+    pub spill_threshold: usize,
+    pub temp_dir: PathBuf,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        let max_body_size = RequestLimits::default().max_body_size;
+        MultipartLimits {
+            max_part_size: max_body_size,
+            max_total_size: max_body_size,
+            spill_threshold: max_body_size / 4,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> std::io::Result<(TempFile, File)> {
+        let mut name = [0u8; TEMP_FILE_NAME_BYTES];
+        if let Err(err) = getrandom::getrandom(&mut name) {
+            return Err(std::io::Error::other(err.to_string()));
+        }
+        let path = dir.join(format!("rusttp-multipart-{}", URL_SAFE_NO_PAD.encode(name)));
+
+        let mut options = File::options();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path)?;
+        Ok((TempFile { path }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+#[derive(Debug)]
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    headers: HeaderMap,
+    size: usize,
+    data: PartData,
+}
+
+impl Part {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn data(&self) -> &PartData {
+        &self.data
+    }
+
+    pub fn bytes(&self) -> std::io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(bytes.clone()),
+            PartData::File(temp_file) => fs::read(temp_file.path())
+        }
+    }
+
+    pub fn persist<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        match &self.data {
+            PartData::Memory(bytes) => fs::write(path, bytes),
+            PartData::File(temp_file) => fs::copy(temp_file.path(), path).map(|_| ())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Multipart {
+    parts: Vec<Part>,
+}
+
+impl Multipart {
+    pub fn from_request(request: &Request, limits: MultipartLimits) -> RequestResult<Multipart, RequestErrors> {
+        let boundary = match request.content_type() {
+            Some(content_type) if content_type == "multipart/form-data" => boundary(request)?,
+            Some(content_type) => return Err(RequestErrors::ContentType { content_type }),
+            None => return Err(RequestErrors::Multipart { reason: String::from("missing Content-Type") })
+        };
+
+        let parts = match request.body_stream() {
+            Some(body) => read_parts(MultipartReader::new(body, &boundary, limits))?,
+            None => read_parts(MultipartReader::new(request.bytes(), &boundary, limits))?
+        };
+
+        Ok(Multipart { parts })
+    }
+
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item=&Part> {
+        self.parts.iter().filter(|part| !part.is_file())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item=&Part> {
+        self.parts.iter().filter(|part| part.is_file())
+    }
+
+    pub fn field(&self, name: &str) -> Option<String> {
+        self.fields()
+            .find(|part| part.name() == name)
+            .and_then(|part| part.bytes().ok())
+            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    pub fn file(&self, name: &str) -> Option<&Part> {
+        self.files().find(|part| part.name() == name)
+    }
+}
+
+pub struct MultipartReader<R: Read> {
+    stream: R,
+    buffer: Vec<u8>,
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    total_size: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: Read> MultipartReader<R> {
+    pub fn new(stream: R, boundary: &str, limits: MultipartLimits) -> MultipartReader<R> {
+        MultipartReader {
+            stream,
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            limits,
+            total_size: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    pub fn next_part(&mut self) -> RequestResult<Option<Part>, RequestErrors> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        if !self.started {
+            self.skip_preamble()?;
+            self.started = true;
+        }
+
+        self.fill_at_least(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+
+        while self.buffer.first() == Some(&b' ') || self.buffer.first() == Some(&b'\t') {
+            self.buffer.remove(0);
+            self.fill_at_least(2)?;
+        }
+        if !self.buffer.starts_with(b"\r\n") {
+            return Err(multipart_error("boundary is not followed by CRLF"));
+        }
+        self.buffer.drain(..2);
+
+        let headers = self.read_part_headers()?;
+        let (name, filename) = parse_content_disposition(&headers)?;
+        let content_type = headers.get("Content-Type").map(String::from);
+        let (size, data) = self.read_part_body()?;
+
+        Ok(Some(Part { name, filename, content_type, headers, size, data }))
+    }
+
+    fn skip_preamble(&mut self) -> RequestResult<(), RequestErrors> {
+        loop {
+            if let Some(index) = find(&self.buffer, &self.delimiter) {
+                self.buffer.drain(..index + self.delimiter.len());
+                return Ok(());
+            }
+
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let discard = self.buffer.len() - keep;
+                self.buffer.drain(..discard);
+            }
+
+            if self.fill_buffer()? == 0 {
+                return Err(multipart_error("missing opening boundary"));
+            }
+        }
+    }
+
+    fn read_part_headers(&mut self) -> RequestResult<HeaderMap, RequestErrors> {
+        let mut headers = HeaderMap::new();
+
+        if self.fill_at_least(2).is_ok() && self.buffer.starts_with(b"\r\n") {
+            self.buffer.drain(..2);
+            return Ok(headers);
+        }
+
+        let raw_headers = loop {
+            if let Some(index) = find(&self.buffer, HEADER_TERMINATOR) {
+                let raw: Vec<u8> = self.buffer.drain(..index + HEADER_TERMINATOR.len()).collect();
+                break String::from_utf8_lossy(&raw[..index]).to_string();
+            }
+
+            if self.buffer.len() > MAX_PART_HEADER_SIZE {
+                return Err(multipart_error("part headers are too large"));
+            }
+
+            if self.fill_buffer()? == 0 {
+                return Err(multipart_error("unexpected end of part headers"));
+            }
+        };
+
+        for line in raw_headers.split("\r\n") {
+            match HeaderMap::parse_line(line) {
+                Some((name, value)) => headers.append(name, value),
+                None => return Err(multipart_error(&format!("invalid part header: {}", line)))
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn read_part_body(&mut self) -> RequestResult<(usize, PartData), RequestErrors> {
+        let mut sink = PartSink { size: 0, memory: Vec::new(), file: None };
+
+        loop {
+            if let Some(index) = find(&self.buffer, &self.delimiter) {
+                let data: Vec<u8> = self.buffer.drain(..index).collect();
+                self.write_part(&mut sink, &data)?;
+                self.buffer.drain(..self.delimiter.len());
+                break;
+            }
+
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let data: Vec<u8> = self.buffer.drain(..self.buffer.len() - keep).collect();
+                self.write_part(&mut sink, &data)?;
+            }
+
+            if self.fill_buffer()? == 0 {
+                return Err(multipart_error("missing closing boundary"));
+            }
+        }
+
+        let data = match sink.file {
+            Some((temp_file, _)) => PartData::File(temp_file),
+            None => PartData::Memory(sink.memory)
+        };
+        Ok((sink.size, data))
+    }
+
+    fn write_part(&mut self, sink: &mut PartSink, data: &[u8]) -> RequestResult<(), RequestErrors> {
+        sink.size += data.len();
+        self.total_size += data.len();
+
+        if sink.size > self.limits.max_part_size {
+            return Err(RequestErrors::PayloadTooLarge { size: sink.size, limit: self.limits.max_part_size });
+        }
+        if self.total_size > self.limits.max_total_size {
+            return Err(RequestErrors::PayloadTooLarge { size: self.total_size, limit: self.limits.max_total_size });
+        }
+
+        if sink.file.is_none() && sink.size > self.limits.spill_threshold {
+            let (temp_file, mut file) = TempFile::create(&self.limits.temp_dir).map_err(temp_file_error)?;
+            file.write_all(&sink.memory).map_err(temp_file_error)?;
+            sink.memory = Vec::new();
+            sink.file = Some((temp_file, file));
+        }
+
+        match &mut sink.file {
+            Some((_, file)) => file.write_all(data).map_err(temp_file_error),
+            None => {
+                sink.memory.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    fn fill_at_least(&mut self, size: usize) -> RequestResult<(), RequestErrors> {
+        while self.buffer.len() < size {
+            if self.fill_buffer()? == 0 {
+                return Err(multipart_error("unexpected end of body"));
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_buffer(&mut self) -> RequestResult<usize, RequestErrors> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(size) => {
+                    self.buffer.extend_from_slice(&chunk[..size]);
+                    return Ok(size);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(multipart_error(&err.to_string()))
+            }
+        }
+    }
+}
+
+struct PartSink {
+    size: usize,
+    memory: Vec<u8>,
+    file: Option<(TempFile, File)>,
+}
+
+fn boundary(request: &Request) -> RequestResult<String, RequestErrors> {
+    let content_type = request.header("Content-Type").unwrap_or("");
+    let boundary = split_parameters(content_type).into_iter().skip(1)
+        .filter_map(|parameter| {
+            parameter.split_once('=').map(|(name, value)| (name.trim().to_ascii_lowercase(), unquote(value.trim())))
+        })
+        .find(|(name, _)| name == "boundary")
+        .map(|(_, value)| value);
+
+    match boundary {
+        Some(boundary) if !boundary.is_empty() && boundary.len() <= 70 => Ok(boundary),
+        _ => Err(multipart_error("missing or invalid boundary"))
+    }
+}
+
+fn parse_content_disposition(headers: &HeaderMap) -> RequestResult<(String, Option<String>), RequestErrors> {
+    let disposition = match headers.get("Content-Disposition") {
+        Some(disposition) => disposition,
+        None => return Err(multipart_error("part without Content-Disposition"))
+    };
+
+    let mut name: Option<String> = None;
+    let mut filename: Option<String> = None;
+    let mut extended_filename: Option<String> = None;
+
+    for parameter in split_parameters(disposition).into_iter().skip(1) {
+        let (key, value) = match parameter.split_once('=') {
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+            None => continue
+        };
+
+        match key.as_str() {
+            "name" => name = Some(unquote(value)),
+            "filename" => filename = Some(unquote(value)),
+            "filename*" => {
+                extended_filename = value.split_once("''")
+                    .map(|(_, encoded)| percent_decode_str(encoded).decode_utf8_lossy().to_string());
+            }
+            _ => {}
+        }
+    }
+
+    match name {
+        Some(name) => Ok((name, extended_filename.or(filename))),
+        None => Err(multipart_error("part without a name"))
+    }
+}
+
+fn split_parameters(value: &str) -> Vec<String> {
+    let mut parameters: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for character in value.chars() {
+        if escaped {
+            escaped = false;
+        } else if quoted && character == '\\' {
+            escaped = true;
+        } else if character == '"' {
+            quoted = !quoted;
+        } else if character == ';' && !quoted {
+            parameters.push(current.trim().to_string());
+            current = String::new();
+            continue;
+        }
+        current.push(character);
+    }
+    parameters.push(current.trim().to_string());
+    parameters
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut unquoted = String::new();
+        let mut escaped = false;
+        for character in value[1..value.len() - 1].chars() {
+            if !escaped && character == '\\' {
+                escaped = true;
+                continue;
+            }
+            escaped = false;
+            unquoted.push(character);
+        }
+        unquoted
+    } else {
+        String::from(value)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn read_parts<R: Read>(mut reader: MultipartReader<R>) -> RequestResult<Vec<Part>, RequestErrors> {
+    let mut parts: Vec<Part> = Vec::new();
+    while let Some(part) = reader.next_part()? {
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+fn multipart_error(reason: &str) -> RequestErrors {
+    RequestErrors::Multipart { reason: String::from(reason) }
+}
+
+fn temp_file_error(err: std::io::Error) -> RequestErrors {
+    RequestErrors::TempFile { reason: err.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BODY: &str = "preamble\r\n\
+                        --XyZ\r\n\
+                        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                        Quarterly report\r\n\
+                        --XyZ\r\n\
+                        Content-Disposition: form-data; name=\"report\"; filename=\"q1; final.csv\"\r\n\
+                        Content-Type: text/csv\r\n\r\n\
+                        a,b\r\n1,2\r\n\
+                        --XyZ--\r\n";
+
+    fn request(body: &str, boundary: &str) -> Request {
+        let raw = format!("POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary={}\r\n\r\n{}", boundary, body);
+        Request::from_str(&raw).unwrap()
+    }
+
+    #[test]
+    fn parse_fields_and_files() {
+        let multipart = request(BODY, "XyZ").multipart().unwrap();
+
+        assert_eq!(multipart.parts().len(), 2);
+        assert_eq!(multipart.field("title"), Some(String::from("Quarterly report")));
+
+        let report = multipart.file("report").unwrap();
+        assert_eq!(report.filename(), Some("q1; final.csv"));
+        assert_eq!(report.content_type(), Some("text/csv"));
+        assert_eq!(report.bytes().unwrap(), b"a,b\r\n1,2");
+        assert!(matches!(report.data(), PartData::Memory(_)));
+    }
+
+    #[test]
+    fn spill_large_parts_to_temp_files() {
+        let limits = MultipartLimits { spill_threshold: 4, ..MultipartLimits::default() };
+        let multipart = request(BODY, "\"XyZ\"").multipart_with_limits(limits).unwrap();
+
+        let report = multipart.file("report").unwrap();
+        let path = match report.data() {
+            PartData::File(temp_file) => temp_file.path().to_path_buf(),
+            PartData::Memory(_) => panic!("Expected the report to be spilled to disk")
+        };
+        assert_eq!(fs::read(&path).unwrap(), b"a,b\r\n1,2");
+        assert_eq!(report.size(), 8);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        drop(multipart);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn streaming_reader() {
+        let mut reader = MultipartReader::new(BODY.as_bytes(), "XyZ", MultipartLimits::default());
+
+        assert_eq!(reader.next_part().unwrap().unwrap().name(), "title");
+        assert_eq!(reader.next_part().unwrap().unwrap().name(), "report");
+        assert!(reader.next_part().unwrap().is_none());
+        assert!(reader.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn part_and_total_limits() {
+        let limits = MultipartLimits { max_part_size: 10, ..MultipartLimits::default() };
+        assert_eq!(request(BODY, "XyZ").multipart_with_limits(limits).unwrap_err(),
+                   RequestErrors::PayloadTooLarge { size: 16, limit: 10 });
+
+        let limits = MultipartLimits { max_total_size: 20, ..MultipartLimits::default() };
+        assert_eq!(request(BODY, "XyZ").multipart_with_limits(limits).unwrap_err(),
+                   RequestErrors::PayloadTooLarge { size: 24, limit: 20 });
+    }
+
+    #[test]
+    fn malformed_bodies() {
+        let truncated = &BODY[..BODY.len() - 10];
+        assert!(matches!(request(truncated, "XyZ").multipart(), Err(RequestErrors::Multipart { .. })));
+        assert!(matches!(request(BODY, "Other").multipart(), Err(RequestErrors::Multipart { .. })));
+
+        let raw = format!("POST / HTTP/1.1\r\nContent-Type: multipart/form-data\r\n\r\n{}", BODY);
+        assert!(matches!(Request::from_str(&raw).unwrap().multipart(), Err(RequestErrors::Multipart { .. })));
+
+        let raw = format!("POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n{}", BODY);
+        assert!(matches!(Request::from_str(&raw).unwrap().multipart(), Err(RequestErrors::ContentType { .. })));
+    }
+}
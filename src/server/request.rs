@@ -1,88 +1,327 @@
-use crate::http_enums::RequestMethod;
+use crate::http_enums::{HttpVersion, RequestMethod};
 use std::collections::HashMap;
-use serde_json::Value;
 use crate::server_errors::{RequestResult, RequestErrors};
 use std::fmt;
+use std::str::FromStr;
+use serde::de::DeserializeOwned;
+use crate::multipart::{Multipart, MultipartLimits};
+use crate::connection::PeerAddr;
+use crate::header_map::HeaderMap;
+use crate::cookie::CookieJar;
+use crate::session::Session;
+use crate::request_reader::RequestBody;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
 
 #[derive(Debug)]
 pub struct Request {
     method: RequestMethod,
+    version: HttpVersion,
     path: String,
-    headers: HashMap<String, String>,
-    body: Option<Value>,
+    query: String,
+    query_params: HashMap<String, Vec<String>>,
+    headers: HeaderMap,
+    trailers: HeaderMap,
+    body: Vec<u8>,
+    params: HashMap<String, String>,
+    peer_addr: PeerAddr,
+    session: Option<Session>,
+    body_stream: Mutex<Option<RequestBody>>,
 }
 
 impl fmt::Display for Request {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "method: {}; path: {}, headers: {:?}, body: {:?}", self.method,
-               self.path, self.headers, self.body)
+        write!(f, "method: {}; version: {}; path: {}, headers: {:?}, body: {} bytes", self.method,
+               self.version, self.path, self.headers, self.body.len())
     }
 }
 
 impl Request {
-    pub fn from_str(request_txt: &str) -> RequestResult<Request, RequestErrors> {
-        let mut headers: HashMap<String, String> = HashMap::new();
-        let mut header_result: RequestResult<(RequestMethod, String), RequestErrors> = {
-            Err(RequestErrors::UnparsedRequest { request: "".to_string() })
-        };
+    pub fn method(&self) -> &RequestMethod {
+        &self.method
+    }
 
-        let (raw_headers, raw_body) = match separate_body_from_header(request_txt) {
-            Ok(separated) => (separated.0, separated.1),
-            Err(e) => return Err(e)
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query_string(&self) -> &str {
+        &self.query
+    }
+
+    pub fn query_params(&self) -> &HashMap<String, Vec<String>> {
+        &self.query_params
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query_params.get(name)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
+    pub fn query<T: DeserializeOwned>(&self) -> RequestResult<T, RequestErrors> {
+        match serde_urlencoded::from_str(&self.query) {
+            Ok(value) => Ok(value),
+            Err(err) => Err(RequestErrors::ParseQuery { query: self.query.clone(), reason: err.to_string() })
+        }
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub fn cookies(&self) -> CookieJar {
+        CookieJar::parse(self.headers.get_all("Cookie"))
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        let connection = match self.header("Connection") {
+            Some(connection) => connection,
+            None => return self.version.keep_alive_by_default()
         };
 
-        for line in raw_headers.lines() {
-            if line.contains("HTTP/1.1") {
-                header_result = parse_request_header(line);
-            } else if line.contains(":") {
-                let separated_header: Vec<&str> = line.split(": ").collect();
-                headers.insert(separated_header[0].parse().unwrap(), separated_header[1].parse().unwrap());
-            }
+        let has_token = |name: &str| connection.split(',').any(|token| token.trim().eq_ignore_ascii_case(name));
+        if has_token("close") {
+            false
+        } else if has_token("keep-alive") {
+            true
+        } else {
+            self.version.keep_alive_by_default()
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn body_stream(&self) -> Option<RequestBody> {
+        match self.body_stream.lock() {
+            Ok(mut body_stream) => body_stream.take(),
+            Err(_) => None
         }
+    }
 
-        let body_parse_result: RequestResult<Option<Value>, RequestErrors> = match raw_body {
-            "" => Ok(None),
-            json => {
-                match serde_json::from_str(json) {
-                    Ok(value) => Ok(Some(value)),
-                    Err(_) => {
-                        Err(RequestErrors::ParseJson { json: String::from(json) })
-                    }
+    pub(crate) fn set_body_stream(&mut self, body_stream: RequestBody) {
+        if let Ok(slot) = self.body_stream.get_mut() {
+            *slot = Some(body_stream);
+        }
+    }
+
+    pub(crate) fn unread_body(&self) -> Option<Arc<AtomicUsize>> {
+        match self.body_stream.lock() {
+            Ok(body_stream) => body_stream.as_ref().map(RequestBody::remaining_handle),
+            Err(_) => None
+        }
+    }
+
+    pub fn content_type(&self) -> Option<String> {
+        self.header("Content-Type")
+            .map(|content_type| content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+    }
+
+    pub fn charset(&self) -> Option<String> {
+        let content_type = self.header("Content-Type")?;
+        content_type.split(';').skip(1)
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_ascii_lowercase())
+    }
+
+    pub fn text(&self) -> RequestResult<String, RequestErrors> {
+        let charset = self.charset().unwrap_or_else(|| String::from("utf-8"));
+
+        match charset.as_str() {
+            "utf-8" | "utf8" => match String::from_utf8(self.body.clone()) {
+                Ok(text) => Ok(text),
+                Err(_) => Err(RequestErrors::InvalidText { charset })
+            },
+            "us-ascii" | "ascii" => {
+                if self.body.is_ascii() {
+                    Ok(self.body.iter().map(|byte| *byte as char).collect())
+                } else {
+                    Err(RequestErrors::InvalidText { charset })
                 }
             }
-        };
+            "iso-8859-1" | "latin1" | "latin-1" => Ok(self.body.iter().map(|byte| *byte as char).collect()),
+            _ => Err(RequestErrors::UnsupportedCharset { charset })
+        }
+    }
 
-        let (method, path) = match header_result {
-            Ok(result) => {
-                (result.0, result.1)
-            }
-            Err(err) => {
-                return Err(err);
+    pub fn json<T: DeserializeOwned>(&self) -> RequestResult<T, RequestErrors> {
+        self.expect_content_type(|content_type| content_type == "application/json" || content_type.ends_with("+json"))?;
+
+        let mut deserializer = serde_json::Deserializer::from_slice(&self.body);
+        let result = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|err| (err.path().to_string(), err.into_inner()))
+            .and_then(|value| match deserializer.end() {
+                Ok(()) => Ok(value),
+                Err(err) => Err((String::from("."), err))
+            });
+
+        match result {
+            Ok(value) => Ok(value),
+            Err((path, err)) if err.classify() == serde_json::error::Category::Data => {
+                Err(RequestErrors::JsonField { path, reason: err.to_string() })
             }
+            Err(_) => Err(RequestErrors::ParseJson { json: String::from_utf8_lossy(&self.body).to_string() })
+        }
+    }
+
+    pub fn form<T: DeserializeOwned>(&self) -> RequestResult<T, RequestErrors> {
+        self.expect_content_type(|content_type| content_type == "application/x-www-form-urlencoded")?;
+
+        match serde_urlencoded::from_bytes(&self.body) {
+            Ok(value) => Ok(value),
+            Err(err) => Err(RequestErrors::ParseForm { reason: err.to_string() })
+        }
+    }
+
+    pub fn multipart(&self) -> RequestResult<Multipart, RequestErrors> {
+        Multipart::from_request(self, MultipartLimits::default())
+    }
+
+    pub fn multipart_with_limits(&self, limits: MultipartLimits) -> RequestResult<Multipart, RequestErrors> {
+        Multipart::from_request(self, limits)
+    }
+
+    fn expect_content_type<F: Fn(&str) -> bool>(&self, accepts: F) -> RequestResult<(), RequestErrors> {
+        match self.content_type() {
+            Some(content_type) if !accepts(&content_type) => Err(RequestErrors::ContentType { content_type }),
+            _ => Ok(())
+        }
+    }
+
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+
+    pub fn param<T: FromStr>(&self, name: &str) -> RequestResult<T, RequestErrors> {
+        let value = match self.params.get(name) {
+            Some(value) => value,
+            None => return Err(RequestErrors::MissingParam { name: String::from(name) })
         };
 
-        let body: Option<Value> = match body_parse_result {
-            Ok(result) => {
-                result
-            }
-            Err(err) => {
-                return Err(err);
-            }
+        match value.parse::<T>() {
+            Ok(parsed) => Ok(parsed),
+            Err(_) => Err(RequestErrors::InvalidParam { name: String::from(name), value: value.clone() })
+        }
+    }
+
+    pub fn peer_addr(&self) -> &PeerAddr {
+        &self.peer_addr
+    }
+
+    pub(crate) fn set_peer_addr(&mut self, peer_addr: PeerAddr) {
+        self.peer_addr = peer_addr;
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    pub(crate) fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    pub(crate) fn set_params(&mut self, params: HashMap<String, String>) {
+        self.params = params;
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(request_txt: &str) -> RequestResult<Request, RequestErrors> {
+        let (raw_headers, raw_body) = match separate_body_from_header(request_txt) {
+            Ok(separated) => (separated.0, separated.1),
+            Err(e) => return Err(e)
+        };
+
+        let mut request = Request::from_head(raw_headers)?;
+        request.set_body(raw_body.as_bytes().to_vec());
+        Ok(request)
+    }
+
+    pub(crate) fn from_head(raw_headers: &str) -> RequestResult<Request, RequestErrors> {
+        let mut headers = HeaderMap::new();
+        let mut lines = raw_headers.lines().skip_while(|line| line.is_empty());
+
+        let (method, target, version) = match lines.next() {
+            Some(line) => parse_request_header(line)?,
+            None => return Err(RequestErrors::UnparsedRequest { request: String::from(raw_headers) })
         };
 
+        for line in lines {
+            match HeaderMap::parse_line(line) {
+                Some((name, value)) => headers.append(name, value),
+                None => return Err(RequestErrors::HTTPHeader { request: String::from(line) })
+            }
+        }
+
+        let (path, query) = split_request_target(&target);
+        let query_params = parse_query(query);
+
         Ok(Request {
             method,
-            path,
+            version,
+            path: String::from(path),
+            query: String::from(query),
+            query_params,
             headers,
-            body,
+            trailers: HeaderMap::new(),
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer_addr: PeerAddr::Unknown,
+            session: None,
+            body_stream: Mutex::new(None),
         })
     }
+
+    pub(crate) fn content_length(&self) -> RequestResult<usize, RequestErrors> {
+        let values = self.headers.get_all("Content-Length");
+        let mut content_length: Option<usize> = None;
+
+        for value in values.iter() {
+            for length in value.split(',').map(|length| length.trim()) {
+                let parsed = match length.parse::<usize>() {
+                    Ok(parsed) if length.bytes().all(|byte| byte.is_ascii_digit()) => parsed,
+                    _ => return Err(RequestErrors::ContentLength { length: String::from(*value) })
+                };
+                if content_length.is_some_and(|previous| previous != parsed) {
+                    return Err(RequestErrors::ContentLength { length: values.join(", ") });
+                }
+                content_length = Some(parsed);
+            }
+        }
+
+        Ok(content_length.unwrap_or(0))
+    }
+
+    pub(crate) fn add_trailer(&mut self, name: &str, value: &str) {
+        self.trailers.append(name, value);
+    }
+
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
 }
 
-fn parse_request_header(header_line: &str) -> RequestResult<(RequestMethod, String), RequestErrors> {
-    let separated_header: Vec<&str> = header_line.split(" ").collect();
+fn parse_request_header(header_line: &str) -> RequestResult<(RequestMethod, String, HttpVersion), RequestErrors> {
+    let separated_header: Vec<&str> = header_line.split(' ').collect();
+    if separated_header.len() != 3 {
+        return Err(RequestErrors::HTTPHeader { request: String::from(header_line) });
+    }
 
-    let method_str: &str = match separated_header.get(0) {
+    let method_str: &str = match separated_header.first() {
         Some(path) => {
             path
         }
@@ -107,11 +346,39 @@ fn parse_request_header(header_line: &str) -> RequestResult<(RequestMethod, Stri
         }
     };
 
-    Ok((request_method, String::from(path)))
+    let version_str = separated_header[2];
+    let version = match HttpVersion::from_str(version_str) {
+        Some(version) => version,
+        None if version_str.starts_with("HTTP/") => {
+            return Err(RequestErrors::HTTPVersion { version: String::from(version_str) });
+        }
+        None => {
+            return Err(RequestErrors::HTTPHeader { request: String::from(header_line) });
+        }
+    };
+
+    Ok((request_method, String::from(path), version))
+}
+
+fn split_request_target(target: &str) -> (&str, &str) {
+    match target.find('?') {
+        Some(index) => (&target[..index], &target[index + 1..]),
+        None => (target, "")
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
+    let mut query_params: HashMap<String, Vec<String>> = HashMap::new();
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap_or_default();
+
+    for (key, value) in pairs {
+        query_params.entry(key).or_default().push(value);
+    }
+    query_params
 }
 
 fn separate_body_from_header(request_txt: &str) -> RequestResult<(&str, &str), RequestErrors> {
-    let separated_vec: Vec<&str> = request_txt.split("\r\n\r\n").collect();
+    let separated_vec: Vec<&str> = request_txt.splitn(2, "\r\n\r\n").collect();
     let request_header = separated_vec[0];
 
     let request_body = match separated_vec.get(1) {
@@ -125,6 +392,7 @@ fn separate_body_from_header(request_txt: &str) -> RequestResult<(&str, &str), R
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::http_enums::ResponseStatusCode;
 
     #[test]
     fn parse_request_ok() {
@@ -170,15 +438,175 @@ mod test {
     #[test]
     fn invalid_json() {
         let to_parse = "POST / HTTP/1.1\r\nHost: localhost:8378\r\nUser-Agent: insomnia/2021.3.0\r\nAccept: */*\r\n\r\nhey";
-        let error = match Request::from_str(to_parse) {
-            Ok(req) => {
-                println!("An error occurred: {}", req);
-                panic!("{}", req)
-            }
+        let request = Request::from_str(to_parse).unwrap();
+        let error = match request.json::<serde_json::Value>() {
+            Ok(value) => panic!("{}", value),
             Err(e) => e
         };
         assert_eq!(error, RequestErrors::ParseJson { json: String::from("hey") })
     }
+
+    #[test]
+    fn raw_and_text_body() {
+        let request = Request::from_str("POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\nolá").unwrap();
+        assert_eq!(request.bytes(), "olá".as_bytes());
+        assert_eq!(request.text(), Ok(String::from("olá")));
+        assert_eq!(request.json::<serde_json::Value>(), Err(RequestErrors::ContentType { content_type: String::from("text/plain") }));
+
+        let mut request = Request::from_str("POST / HTTP/1.1\r\nContent-Type: text/plain; charset=ISO-8859-1\r\n\r\n").unwrap();
+        request.set_body(vec![b'o', b'l', 0xE1]);
+        assert_eq!(request.charset(), Some(String::from("iso-8859-1")));
+        assert_eq!(request.text(), Ok(String::from("olá")));
+
+        let request = Request::from_str("POST / HTTP/1.1\r\nContent-Type: text/plain; charset=\"koi8-r\"\r\n\r\nx").unwrap();
+        assert_eq!(request.text(), Err(RequestErrors::UnsupportedCharset { charset: String::from("koi8-r") }));
+    }
+
+    #[test]
+    fn json_body() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct User {
+            id: u32,
+            name: String,
+        }
+
+        let to_parse = "POST / HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{\"id\":1,\"name\":\"Vand\"}";
+        let request = Request::from_str(to_parse).unwrap();
+        assert_eq!(request.json::<User>(), Ok(User { id: 1, name: String::from("Vand") }));
+    }
+
+    #[test]
+    fn json_field_errors() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Address {
+            zip: u32,
+        }
+
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct User {
+            name: String,
+            addresses: Vec<Address>,
+        }
+
+        let to_parse = "POST / HTTP/1.1\r\n\r\n{\"name\":\"Vand\",\"addresses\":[{\"zip\":1},{\"zip\":\"x\"}]}";
+        let error = Request::from_str(to_parse).unwrap().json::<User>().unwrap_err();
+        match error {
+            RequestErrors::JsonField { path, .. } => assert_eq!(path, "addresses[1].zip"),
+            other => panic!("Unexpected error {:?}", other)
+        }
+
+        let to_parse = "POST / HTTP/1.1\r\n\r\n{\"name\":\"Vand\",\"addresses\":[]} trailing";
+        assert!(matches!(Request::from_str(to_parse).unwrap().json::<User>(), Err(RequestErrors::ParseJson { .. })));
+    }
+
+    #[test]
+    fn form_body() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Login {
+            user: String,
+            remember: bool,
+        }
+
+        let to_parse = "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nuser=vand+j&remember=true";
+        let request = Request::from_str(to_parse).unwrap();
+        assert_eq!(request.form::<Login>(), Ok(Login { user: String::from("vand j"), remember: true }));
+        assert!(matches!(request.json::<serde_json::Value>(), Err(RequestErrors::ContentType { .. })));
+
+        let request = Request::from_str("POST / HTTP/1.1\r\n\r\nuser=vand").unwrap();
+        assert!(matches!(request.form::<Login>(), Err(RequestErrors::ParseForm { .. })));
+    }
+
+    #[test]
+    fn keep_alive_by_default() {
+        let request = Request::from_str("GET / HTTP/1.1\r\nHost: localhost:8378\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+
+        let request = Request::from_str("GET / HTTP/1.1\r\nconnection: Close\r\n\r\n").unwrap();
+        assert_eq!(request.header("Connection"), Some("Close"));
+        assert!(!request.keep_alive());
+    }
+
+    #[test]
+    fn repeated_and_unspaced_headers() {
+        let request = Request::from_str("GET / HTTP/1.1\r\nAccept:text/html\r\ncontent-type:  application/json \r\nACCEPT: */*\r\n\r\n").unwrap();
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(request.headers().get_all("accept"), vec!["text/html", "*/*"]);
+
+        let error = Request::from_str("GET / HTTP/1.1\r\nHost: a\r\n  folded\r\n\r\n").unwrap_err();
+        assert_eq!(error, RequestErrors::HTTPHeader { request: String::from("  folded") });
+    }
+
+    #[test]
+    fn request_cookies() {
+        let request = Request::from_str("GET / HTTP/1.1\r\nCookie: theme=dark; lang=en\r\ncookie: session=abc\r\n\r\n").unwrap();
+        let cookies = request.cookies();
+        assert_eq!(cookies.get("theme"), Some("dark"));
+        assert_eq!(cookies.get("lang"), Some("en"));
+        assert_eq!(cookies.get("session"), Some("abc"));
+        assert!(Request::from_str("GET / HTTP/1.1\r\n\r\n").unwrap().cookies().is_empty());
+    }
+
+    #[test]
+    fn http_versions() {
+        let request = Request::from_str("GET / HTTP/1.0\r\nHost: localhost:8378\r\n\r\n").unwrap();
+        assert_eq!(request.version(), HttpVersion::HTTP_1_0);
+        assert!(!request.keep_alive());
+
+        let request = Request::from_str("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+
+        let error = Request::from_str("GET / HTTP/2.0\r\n\r\n").unwrap_err();
+        assert_eq!(error, RequestErrors::HTTPVersion { version: String::from("HTTP/2.0") });
+        assert_eq!(error.status_code(), ResponseStatusCode::HTTP_VERSION_NOT_SUPPORTED);
+
+        let error = Request::from_str("GET / HTTP/1.1 extra\r\n\r\n").unwrap_err();
+        assert_eq!(error, RequestErrors::HTTPHeader { request: String::from("GET / HTTP/1.1 extra") });
+    }
+
+    #[test]
+    fn parse_query_string() {
+        let to_parse = "GET /search?q=rust%20http&tag=a&tag=b+c&empty= HTTP/1.1\r\nHost: localhost:8378\r\n\r\n";
+        let request = Request::from_str(to_parse).unwrap();
+
+        assert_eq!(request.path(), "/search");
+        assert_eq!(request.query_string(), "q=rust%20http&tag=a&tag=b+c&empty=");
+        assert_eq!(request.query_param("q"), Some("rust http"));
+        assert_eq!(request.query_params().get("tag").unwrap(), &vec![String::from("a"), String::from("b c")]);
+        assert_eq!(request.query_param("empty"), Some(""));
+        assert_eq!(request.query_param("missing"), None);
+    }
+
+    #[test]
+    fn deserialize_query() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Search {
+            q: String,
+            page: u32,
+            limit: Option<u32>,
+        }
+
+        let request = Request::from_str("GET /search?q=rust&page=2 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.query::<Search>(), Ok(Search { q: String::from("rust"), page: 2, limit: None }));
+
+        let request = Request::from_str("GET /search?q=rust&page=two HTTP/1.1\r\n\r\n").unwrap();
+        assert!(matches!(request.query::<Search>(), Err(RequestErrors::ParseQuery { .. })));
+    }
+
+    #[test]
+    fn typed_params() {
+        let to_parse = "GET /users/42 HTTP/1.1\r\nHost: localhost:8378\r\n\r\n";
+        let mut request = Request::from_str(to_parse).unwrap();
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("id"), String::from("42"));
+        params.insert(String::from("name"), String::from("vand"));
+        request.set_params(params);
+
+        assert_eq!(request.param::<u64>("id"), Ok(42));
+        assert_eq!(request.param::<String>("name"), Ok(String::from("vand")));
+        assert_eq!(request.param::<u64>("name"),
+                   Err(RequestErrors::InvalidParam { name: String::from("name"), value: String::from("vand") }));
+        assert_eq!(request.param::<u64>("missing"), Err(RequestErrors::MissingParam { name: String::from("missing") }));
+    }
 }
 
 /*/*
@@ -0,0 +1,763 @@
+use crate::connection::Connection;
+use crate::header_map::HeaderMap;
+use crate::request::Request;
+use crate::server_errors::{RequestErrors, RequestResult};
+use std::io::{self, Cursor, ErrorKind, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const READ_CHUNK_SIZE: usize = 1024;
+const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
+const LINE_TERMINATOR: &[u8] = b"\r\n";
+const REQUEST_LINE_OVERHEAD: usize = 32;
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct RequestLimits {
+    pub max_uri_length: usize,
+    pub max_header_count: usize,
+    pub max_header_size: usize,
+    pub max_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits {
+            max_uri_length: 8 * 1024,
+            max_header_count: 100,
+            max_header_size: 16 * 1024,
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct RequestTimeouts {
+    pub keep_alive: Duration,
+    pub header: Duration,
+    pub body: Duration,
+    pub write: Duration,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> RequestTimeouts {
+        RequestTimeouts {
+            keep_alive: Duration::from_secs(5),
+            header: Duration::from_secs(10),
+            body: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for &Connection {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        Connection::set_read_timeout(self, timeout)
+    }
+}
+
+impl<T> ReadTimeout for Cursor<T> {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ReadTimeout for &[u8] {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestBody {
+    buffered: Cursor<Vec<u8>>,
+    source: Connection,
+    remaining: Arc<AtomicUsize>,
+    deadline: Instant,
+}
+
+impl RequestBody {
+    fn new(buffered: Vec<u8>, source: Connection, content_length: usize, deadline: Instant) -> RequestBody {
+        let remaining = Arc::new(AtomicUsize::new(content_length - buffered.len()));
+        RequestBody { buffered: Cursor::new(buffered), source, remaining, deadline }
+    }
+
+    pub fn unread(&self) -> usize {
+        let buffered = self.buffered.get_ref().len() - self.buffered.position() as usize;
+        buffered + self.remaining.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn remaining_handle(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.remaining)
+    }
+}
+
+impl Read for RequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.buffered.read(buf)?;
+        let remaining = self.remaining.load(Ordering::SeqCst);
+        if size > 0 || remaining == 0 || buf.is_empty() {
+            return Ok(size);
+        }
+
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "timed out reading the request body"));
+        }
+        self.source.set_read_timeout(Some(timeout))?;
+        let limit = buf.len().min(remaining);
+        match self.source.read(&mut buf[..limit])? {
+            0 => Err(io::Error::new(ErrorKind::UnexpectedEof, format!("expected {} more bytes of body", remaining))),
+            size => {
+                self.remaining.fetch_sub(size, Ordering::SeqCst);
+                Ok(size)
+            }
+        }
+    }
+}
+
+enum ReadPhase {
+    Idle,
+    Header(Instant),
+    Body(Instant),
+}
+
+pub struct RequestReader<R: Read + ReadTimeout> {
+    stream: R,
+    buffer: Vec<u8>,
+    limits: RequestLimits,
+    timeouts: RequestTimeouts,
+}
+
+impl<R: Read + ReadTimeout> RequestReader<R> {
+    pub fn new(stream: R) -> RequestReader<R> {
+        RequestReader::with_limits(stream, RequestLimits::default())
+    }
+
+    pub fn with_limits(stream: R, limits: RequestLimits) -> RequestReader<R> {
+        RequestReader { stream, buffer: Vec::new(), limits, timeouts: RequestTimeouts::default() }
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: RequestTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    pub fn read_request(&mut self) -> RequestResult<Option<Request>, RequestErrors> {
+        self.read_request_with_body_limit(|_| None)
+    }
+
+    pub fn read_request_with_body_limit<F>(&mut self, body_limit: F) -> RequestResult<Option<Request>, RequestErrors>
+        where
+            F: Fn(&Request) -> Option<usize>,
+    {
+        self.read_request_streaming(body_limit, |_| None)
+    }
+
+    pub(crate) fn read_request_streaming<F, S>(&mut self, body_limit: F, body_source: S) -> RequestResult<Option<Request>, RequestErrors>
+        where
+            F: Fn(&Request) -> Option<usize>,
+            S: Fn(&Request) -> Option<Connection>,
+    {
+        let raw_head = match self.read_head() {
+            Ok(Some(raw_head)) => raw_head,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(err)
+        };
+
+        let header_count = raw_head.lines().count().saturating_sub(1);
+        if header_count > self.limits.max_header_count {
+            return Err(RequestErrors::HeaderFieldsTooLarge {
+                reason: format!("{} header fields exceed the limit of {}", header_count, self.limits.max_header_count)
+            });
+        }
+
+        let mut request = Request::from_head(&raw_head)?;
+        let max_body_size = body_limit(&request).unwrap_or(self.limits.max_body_size);
+
+        if request.header("Transfer-Encoding").is_some() && request.header("Content-Length").is_some() {
+            return Err(RequestErrors::MessageFraming {
+                reason: String::from("both Content-Length and Transfer-Encoding are present")
+            });
+        }
+
+        let raw_body = if is_chunked(&request)? {
+            self.read_chunked_body(&mut request, max_body_size, header_count, raw_head.len())?
+        } else {
+            let content_length = request.content_length()?;
+            if content_length > max_body_size {
+                return Err(RequestErrors::PayloadTooLarge { size: content_length, limit: max_body_size });
+            }
+
+            let deadline = Instant::now() + self.timeouts.body;
+            if let Some(source) = body_source(&request).filter(|_| content_length > 0) {
+                let buffered = self.buffer.drain(..content_length.min(self.buffer.len())).collect();
+                request.set_body_stream(RequestBody::new(buffered, source, content_length, deadline));
+                return Ok(Some(request));
+            }
+            self.read_body(content_length, deadline)?
+        };
+
+        request.set_body(raw_body);
+        Ok(Some(request))
+    }
+
+    fn read_head(&mut self) -> RequestResult<Option<String>, RequestErrors> {
+        let mut phase = ReadPhase::Idle;
+
+        loop {
+            if let ReadPhase::Idle = phase {
+                if !self.buffer.is_empty() {
+                    phase = ReadPhase::Header(Instant::now() + self.timeouts.header);
+                }
+            }
+
+            self.check_uri_length()?;
+
+            if let Some(index) = find(&self.buffer, HEADER_TERMINATOR) {
+                if index > self.limits.max_header_size {
+                    return Err(RequestErrors::HeaderFieldsTooLarge {
+                        reason: format!("{} bytes of header exceed the limit of {}", index, self.limits.max_header_size)
+                    });
+                }
+                let head: Vec<u8> = self.buffer.drain(..index + HEADER_TERMINATOR.len()).collect();
+                return Ok(Some(String::from_utf8_lossy(&head[..index]).to_string()));
+            }
+
+            if self.buffer.len() > self.limits.max_header_size + HEADER_TERMINATOR.len() {
+                return Err(RequestErrors::HeaderFieldsTooLarge {
+                    reason: format!("header exceeds the limit of {} bytes", self.limits.max_header_size)
+                });
+            }
+
+            let filled = match self.fill_buffer(&phase) {
+                Ok(filled) => filled,
+                Err(_) if self.buffer.is_empty() => 0,
+                Err(err) => return Err(err)
+            };
+            if filled == 0 {
+                return if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(RequestErrors::IncompleteHeader { received: self.buffer.len() })
+                };
+            }
+        }
+    }
+
+    fn check_uri_length(&self) -> RequestResult<(), RequestErrors> {
+        let uri_length = match find(&self.buffer, LINE_TERMINATOR) {
+            Some(index) => {
+                let request_line = String::from_utf8_lossy(&self.buffer[..index]);
+                request_line.split(' ').nth(1).map(|target| target.len()).unwrap_or(0)
+            }
+            None if self.buffer.len() > self.limits.max_uri_length + REQUEST_LINE_OVERHEAD => self.buffer.len(),
+            None => 0
+        };
+
+        if uri_length > self.limits.max_uri_length {
+            Err(RequestErrors::UriTooLong { length: uri_length, limit: self.limits.max_uri_length })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn discard_body(&mut self, mut remaining: usize) -> RequestResult<(), RequestErrors> {
+        let deadline = Instant::now() + self.timeouts.body;
+        loop {
+            let discarded = remaining.min(self.buffer.len());
+            self.buffer.drain(..discarded);
+            remaining -= discarded;
+            if remaining == 0 {
+                return Ok(());
+            }
+            if self.fill_buffer(&ReadPhase::Body(deadline))? == 0 {
+                return Err(RequestErrors::IncompleteBody { expected: remaining, received: 0 });
+            }
+        }
+    }
+
+    fn read_body(&mut self, content_length: usize, deadline: Instant) -> RequestResult<Vec<u8>, RequestErrors> {
+        while self.buffer.len() < content_length {
+            if self.fill_buffer(&ReadPhase::Body(deadline))? == 0 {
+                return Err(RequestErrors::IncompleteBody { expected: content_length, received: self.buffer.len() });
+            }
+        }
+
+        Ok(self.buffer.drain(..content_length).collect())
+    }
+
+    fn read_chunked_body(&mut self, request: &mut Request, max_body_size: usize, mut field_count: usize,
+                         mut field_size: usize) -> RequestResult<Vec<u8>, RequestErrors> {
+        let deadline = Instant::now() + self.timeouts.body;
+        let mut body: Vec<u8> = Vec::new();
+
+        loop {
+            let size_line = self.read_line(body.len(), deadline)?;
+            let size = parse_chunk_size(&size_line)?;
+
+            if size == 0 {
+                break;
+            }
+            if body.len().saturating_add(size) > max_body_size {
+                return Err(RequestErrors::PayloadTooLarge { size: body.len().saturating_add(size), limit: max_body_size });
+            }
+
+            let mut chunk = self.read_body(size + LINE_TERMINATOR.len(), deadline).map_err(|err| match err {
+                RequestErrors::RequestTimeout { .. } => err,
+                _ => RequestErrors::IncompleteChunkedBody { received: body.len() }
+            })?;
+            if !chunk.ends_with(LINE_TERMINATOR) {
+                return Err(RequestErrors::ChunkedEncoding { reason: String::from("chunk data is not followed by CRLF") });
+            }
+            chunk.truncate(size);
+            body.extend_from_slice(&chunk);
+        }
+
+        loop {
+            let trailer = self.read_line(body.len(), deadline)?;
+            if trailer.is_empty() {
+                break;
+            }
+
+            field_count += 1;
+            field_size += trailer.len() + LINE_TERMINATOR.len();
+            if field_count > self.limits.max_header_count {
+                return Err(RequestErrors::HeaderFieldsTooLarge {
+                    reason: format!("{} header and trailer fields exceed the limit of {}", field_count, self.limits.max_header_count)
+                });
+            }
+            if field_size > self.limits.max_header_size {
+                return Err(RequestErrors::HeaderFieldsTooLarge {
+                    reason: format!("{} bytes of header and trailers exceed the limit of {}", field_size, self.limits.max_header_size)
+                });
+            }
+
+            match HeaderMap::parse_line(&trailer) {
+                Some((name, value)) => request.add_trailer(name, value),
+                None => {
+                    return Err(RequestErrors::ChunkedEncoding { reason: format!("invalid trailer: {}", trailer) });
+                }
+            }
+        }
+
+        Ok(body)
+    }
+
+    fn read_line(&mut self, received: usize, deadline: Instant) -> RequestResult<String, RequestErrors> {
+        loop {
+            if let Some(index) = find(&self.buffer, LINE_TERMINATOR) {
+                let line: Vec<u8> = self.buffer.drain(..index + LINE_TERMINATOR.len()).collect();
+                return Ok(String::from_utf8_lossy(&line[..index]).to_string());
+            }
+
+            if self.buffer.len() > self.limits.max_header_size {
+                return Err(RequestErrors::ChunkedEncoding {
+                    reason: format!("line exceeds the limit of {} bytes", self.limits.max_header_size)
+                });
+            }
+
+            if self.fill_buffer(&ReadPhase::Body(deadline))? == 0 {
+                return Err(RequestErrors::IncompleteChunkedBody { received });
+            }
+        }
+    }
+
+    fn fill_buffer(&mut self, phase: &ReadPhase) -> RequestResult<usize, RequestErrors> {
+        let (timeout, part) = match phase {
+            ReadPhase::Idle => (self.timeouts.keep_alive, "header"),
+            ReadPhase::Header(deadline) => (deadline.saturating_duration_since(Instant::now()), "header"),
+            ReadPhase::Body(deadline) => (deadline.saturating_duration_since(Instant::now()), "body"),
+        };
+        if timeout.is_zero() {
+            return Err(RequestErrors::RequestTimeout { part: String::from(part) });
+        }
+        if self.stream.set_read_timeout(Some(timeout)).is_err() {
+            return Ok(0);
+        }
+
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(size) => {
+                    self.buffer.extend_from_slice(&chunk[..size]);
+                    return Ok(size);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+                    return Err(RequestErrors::RequestTimeout { part: String::from(part) });
+                }
+                Err(_) => return Ok(0)
+            }
+        }
+    }
+}
+
+fn is_chunked(request: &Request) -> RequestResult<bool, RequestErrors> {
+    let values = request.headers().get_all("Transfer-Encoding");
+    if values.is_empty() {
+        return Ok(false);
+    }
+
+    let transfer_encoding = values.join(", ");
+    let codings: Vec<&str> = transfer_encoding.split(',').map(|coding| coding.trim()).filter(|coding| !coding.is_empty()).collect();
+    match codings.as_slice() {
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(true),
+        _ if codings.iter().all(|coding| coding.eq_ignore_ascii_case("chunked")) => {
+            Err(RequestErrors::ChunkedEncoding { reason: format!("invalid transfer encoding: {}", transfer_encoding) })
+        }
+        _ => Err(RequestErrors::TransferEncoding { encoding: transfer_encoding })
+    }
+}
+
+fn parse_chunk_size(size_line: &str) -> RequestResult<usize, RequestErrors> {
+    let size = match size_line.split_once(';') {
+        Some((size, _extensions)) => size.trim_end_matches([' ', '\t']),
+        None => size_line
+    };
+
+    match usize::from_str_radix(size, 16) {
+        Ok(parsed) if size.bytes().all(|byte| byte.is_ascii_hexdigit()) => Ok(parsed),
+        _ => Err(RequestErrors::ChunkedEncoding { reason: format!("invalid chunk size: {}", size_line) })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http_enums::RequestMethod;
+    use std::io::Cursor;
+
+    struct Trickle {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl ReadTimeout for Trickle {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.position >= self.data.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.data[self.position];
+            self.position += 1;
+            Ok(1)
+        }
+    }
+
+    struct Stalled {
+        data: Cursor<Vec<u8>>,
+    }
+
+    impl ReadTimeout for Stalled {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Stalled {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.data.read(buf)? {
+                0 => Err(std::io::Error::from(ErrorKind::WouldBlock)),
+                size => Ok(size)
+            }
+        }
+    }
+
+    struct Dripping {
+        head: Cursor<Vec<u8>>,
+        body: Vec<u8>,
+        position: usize,
+    }
+
+    impl ReadTimeout for Dripping {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Dripping {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let size = self.head.read(buf)?;
+            if size > 0 || self.position >= self.body.len() || buf.is_empty() {
+                return Ok(size);
+            }
+            std::thread::sleep(Duration::from_millis(30));
+            buf[0] = self.body[self.position];
+            self.position += 1;
+            Ok(1)
+        }
+    }
+
+    fn dripping(head: &str, body: &str) -> RequestResult<Option<Request>, RequestErrors> {
+        let stream = Dripping { head: Cursor::new(head.as_bytes().to_vec()), body: body.as_bytes().to_vec(), position: 0 };
+        let mut reader = RequestReader::new(stream);
+        reader.set_timeouts(RequestTimeouts { body: Duration::from_millis(100), ..RequestTimeouts::default() });
+        reader.read_request()
+    }
+
+    fn stalled(raw: &str) -> RequestResult<Option<Request>, RequestErrors> {
+        RequestReader::new(Stalled { data: Cursor::new(raw.as_bytes().to_vec()) }).read_request()
+    }
+
+    #[test]
+    fn stalled_reads_time_out() {
+        assert!(matches!(stalled(""), Ok(None)));
+        assert_eq!(stalled("GET / HTTP/1.1\r\nHost").unwrap_err(),
+                   RequestErrors::RequestTimeout { part: String::from("header") });
+        assert_eq!(stalled("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n{}").unwrap_err(),
+                   RequestErrors::RequestTimeout { part: String::from("body") });
+        assert_eq!(stalled("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab").unwrap_err(),
+                   RequestErrors::RequestTimeout { part: String::from("body") });
+    }
+
+    #[test]
+    fn body_deadline_covers_the_whole_body() {
+        assert_eq!(dripping("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n", "0123456789").unwrap_err(),
+                   RequestErrors::RequestTimeout { part: String::from("body") });
+        assert_eq!(dripping("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n", "A\r\n0123456789\r\n0\r\n\r\n").unwrap_err(),
+                   RequestErrors::RequestTimeout { part: String::from("body") });
+        assert_eq!(dripping("POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n", "{}").unwrap().unwrap().bytes(), b"{}");
+    }
+
+    #[test]
+    fn read_body_larger_than_chunk() {
+        let json = format!("{{\"data\":\"{}\"}}", "x".repeat(4000));
+        let raw = format!("POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", json.len(), json);
+        let mut reader = RequestReader::new(Cursor::new(raw.into_bytes()));
+
+        let request = reader.read_request().unwrap().unwrap();
+        assert_eq!(request.method(), &RequestMethod::POST);
+        assert_eq!(request.json::<serde_json::Value>().unwrap()["data"].as_str().unwrap().len(), 4000);
+        assert!(matches!(reader.read_request(), Ok(None)));
+    }
+
+    #[test]
+    fn read_across_many_reads() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        let mut reader = RequestReader::new(Trickle { data: raw.as_bytes().to_vec(), position: 0 });
+
+        let request = reader.read_request().unwrap().unwrap();
+        assert_eq!(request.json::<serde_json::Value>().unwrap()["a"], 1);
+    }
+
+    #[test]
+    fn read_pipelined_requests() {
+        let raw = "GET /one HTTP/1.1\r\n\r\nPOST /two HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}GET /three HTTP/1.1\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert_eq!(reader.read_request().unwrap().unwrap().path(), "/one");
+        assert_eq!(reader.read_request().unwrap().unwrap().path(), "/two");
+        assert_eq!(reader.read_request().unwrap().unwrap().path(), "/three");
+        assert!(matches!(reader.read_request(), Ok(None)));
+    }
+
+    #[test]
+    fn body_never_arrives() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 20\r\n\r\n{\"a\":1}";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::IncompleteBody { expected: 20, received: 7 });
+    }
+
+    #[test]
+    fn header_never_finishes() {
+        let raw = "GET / HTTP/1.1\r\nHost: local";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::IncompleteHeader { received: raw.len() });
+    }
+
+    #[test]
+    fn invalid_content_length() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::ContentLength { length: String::from("ten") });
+    }
+
+    #[test]
+    fn conflicting_content_lengths() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 5\r\n\r\nhello";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::ContentLength { length: String::from("0, 5") });
+
+        let raw = "POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::ContentLength { length: String::from("+5") });
+
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 5, 5\r\ncontent-length: 5\r\n\r\nhello";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap().unwrap().bytes(), b"hello");
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert!(matches!(reader.read_request(), Err(RequestErrors::MessageFraming { .. })));
+    }
+
+    #[test]
+    fn read_chunked_body() {
+        let raw = "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   4\r\n{\"a\"\r\n\
+                   5;name=value\r\n:[1,2\r\n\
+                   2\r\n]}\r\n\
+                   0\r\n\
+                   Expires: never\r\n\
+                   Authorization: forged\r\n\r\n\
+                   GET /next HTTP/1.1\r\n\r\n";
+        let mut reader = RequestReader::new(Trickle { data: raw.as_bytes().to_vec(), position: 0 });
+
+        let request = reader.read_request().unwrap().unwrap();
+        assert_eq!(request.json::<serde_json::Value>().unwrap()["a"], serde_json::json!([1, 2]));
+        assert_eq!(request.trailers().get("Expires"), Some("never"));
+        assert_eq!(request.trailers().get("Authorization"), Some("forged"));
+        assert_eq!(request.header("Authorization"), None);
+        assert_eq!(reader.read_request().unwrap().unwrap().path(), "/next");
+    }
+
+    #[test]
+    fn only_plain_chunked_is_accepted() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::TransferEncoding { encoding: String::from("gzip, chunked") });
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::TransferEncoding { encoding: String::from("chunked, gzip") });
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert!(matches!(reader.read_request(), Err(RequestErrors::ChunkedEncoding { .. })));
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap().unwrap().bytes(), b"{}");
+    }
+
+    #[test]
+    fn invalid_chunk_size() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n{}\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert!(matches!(reader.read_request(), Err(RequestErrors::ChunkedEncoding { .. })));
+    }
+
+    #[test]
+    fn chunk_size_must_be_hex_digits() {
+        for size in ["+2", " 2", "2 ", "", "0x2"] {
+            let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\n{{}}\r\n0\r\n\r\n", size);
+            let mut reader = RequestReader::new(Cursor::new(raw.into_bytes()));
+            assert!(matches!(reader.read_request(), Err(RequestErrors::ChunkedEncoding { .. })), "accepted {:?}", size);
+        }
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2 ;ext=1\r\n{}\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+        assert_eq!(reader.read_request().unwrap().unwrap().bytes(), b"{}");
+    }
+
+    #[test]
+    fn chunk_without_crlf() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}xx0\r\n\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert!(matches!(reader.read_request(), Err(RequestErrors::ChunkedEncoding { .. })));
+    }
+
+    #[test]
+    fn chunked_body_never_finishes() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n";
+        let mut reader = RequestReader::new(Cursor::new(raw.as_bytes().to_vec()));
+
+        assert_eq!(reader.read_request().unwrap_err(), RequestErrors::IncompleteChunkedBody { received: 2 });
+    }
+
+    fn limited(raw: &str, limits: RequestLimits) -> RequestResult<Option<Request>, RequestErrors> {
+        RequestReader::with_limits(Cursor::new(raw.as_bytes().to_vec()), limits).read_request()
+    }
+
+    #[test]
+    fn uri_too_long() {
+        let limits = RequestLimits { max_uri_length: 16, ..RequestLimits::default() };
+
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(20));
+        assert_eq!(limited(&raw, limits).unwrap_err(), RequestErrors::UriTooLong { length: 21, limit: 16 });
+
+        let raw = format!("GET /{}", "a".repeat(100));
+        assert!(matches!(limited(&raw, limits), Err(RequestErrors::UriTooLong { .. })));
+
+        assert!(limited("GET /short HTTP/1.1\r\n\r\n", limits).is_ok());
+    }
+
+    #[test]
+    fn too_many_headers() {
+        let limits = RequestLimits { max_header_count: 2, ..RequestLimits::default() };
+
+        let raw = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(matches!(limited(raw, limits), Err(RequestErrors::HeaderFieldsTooLarge { .. })));
+        assert!(limited("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n", limits).is_ok());
+    }
+
+    #[test]
+    fn too_many_trailers() {
+        let limits = RequestLimits { max_header_count: 2, ..RequestLimits::default() };
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nA: 1\r\nB: 2\r\n\r\n";
+        assert!(matches!(limited(raw, limits), Err(RequestErrors::HeaderFieldsTooLarge { .. })));
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nA: 1\r\n\r\n";
+        assert!(limited(raw, limits).is_ok());
+
+        let limits = RequestLimits { max_header_size: 64, ..RequestLimits::default() };
+        let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Big: {}\r\n\r\n", "b".repeat(40));
+        assert!(matches!(limited(&raw, limits), Err(RequestErrors::HeaderFieldsTooLarge { .. })));
+    }
+
+    #[test]
+    fn header_too_large() {
+        let limits = RequestLimits { max_header_size: 64, ..RequestLimits::default() };
+
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "c".repeat(100));
+        assert!(matches!(limited(&raw, limits), Err(RequestErrors::HeaderFieldsTooLarge { .. })));
+
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}", "c".repeat(1000));
+        assert!(matches!(limited(&raw, limits), Err(RequestErrors::HeaderFieldsTooLarge { .. })));
+    }
+
+    #[test]
+    fn payload_too_large() {
+        let limits = RequestLimits { max_body_size: 4, ..RequestLimits::default() };
+
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        assert_eq!(limited(raw, limits).unwrap_err(), RequestErrors::PayloadTooLarge { size: 7, limit: 4 });
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n5\r\n     \r\n0\r\n\r\n";
+        assert_eq!(limited(raw, limits).unwrap_err(), RequestErrors::PayloadTooLarge { size: 7, limit: 4 });
+    }
+
+    #[test]
+    fn body_limit_override() {
+        let raw = "POST /upload HTTP/1.1\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        let limits = RequestLimits { max_body_size: 4, ..RequestLimits::default() };
+        let mut reader = RequestReader::with_limits(Cursor::new(raw.as_bytes().to_vec()), limits);
+
+        let request = reader.read_request_with_body_limit(|request| {
+            if request.path() == "/upload" { Some(1024) } else { None }
+        });
+        assert_eq!(request.unwrap().unwrap().json::<serde_json::Value>().unwrap()["a"], 1);
+    }
+}
@@ -1,53 +1,210 @@
-use crate::server::http_enums::ResponseStatusCode;
+use crate::server::http_enums::{HttpVersion, RequestMethod, ResponseStatusCode};
 use serde_json::Value;
-use std::collections::HashMap;
-use crate::server_errors::{ResponseErrors, ResponseResult};
+use serde::Serialize;
+use crate::server_errors::{HandlerError, ResponseErrors, ResponseResult};
 use serde_json::Result as serde_result;
 use std::io::prelude::*;
-use std::net::TcpStream;
+use crate::connection::Connection;
+use crate::header_map::HeaderMap;
+use crate::cookie::Cookie;
+
+type SendHook = Box<dyn FnOnce(&mut OutgoingResponse) + Send + 'static>;
+
+pub struct OutgoingResponse {
+    pub status_code: ResponseStatusCode,
+    pub headers: HeaderMap,
+    pub body: Option<Value>,
+}
 
 pub struct Response {
-    stream: TcpStream,
+    stream: Connection,
+    version: HttpVersion,
+    method: RequestMethod,
+    hooks: Vec<SendHook>,
+    cookies: Vec<Cookie>,
+    keep_alive: bool,
+    sent: bool,
 }
 
 impl Response {
-    pub fn new(stream: TcpStream) -> Response {
-        Response {stream}
+    pub fn new<C: Into<Connection>>(stream: C) -> Response {
+        Response { stream: stream.into(), version: HttpVersion::HTTP_1_1, method: RequestMethod::GET, hooks: Vec::new(), cookies: Vec::new(), keep_alive: false, sent: false }
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    pub(crate) fn set_version(&mut self, version: HttpVersion) {
+        self.version = version;
+    }
+
+    pub fn method(&self) -> RequestMethod {
+        self.method
+    }
+
+    pub(crate) fn set_method(&mut self, method: RequestMethod) {
+        self.method = method;
+    }
+
+    pub fn is_sent(&self) -> bool {
+        self.sent
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    pub(crate) fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    pub fn on_send<T>(&mut self, hook: T)
+        where
+            T: FnOnce(&mut OutgoingResponse) + Send + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn set_cookie(&mut self, cookie: Cookie) -> ResponseResult<(), ResponseErrors> {
+        if !cookie.is_valid() {
+            return Err(ResponseErrors::InvalidCookie { name: format!("{:?}", cookie.name()) });
+        }
+        self.cookies.push(cookie);
+        Ok(())
+    }
+
+    pub fn send(&mut self, status_code: ResponseStatusCode) -> ResponseResult<(), ResponseErrors> {
+        self.send_all(status_code, None, None)
+    }
+
+    pub fn send_headers(&mut self, status_code: ResponseStatusCode, headers: Option<HeaderMap>) -> ResponseResult<(), ResponseErrors> {
+        self.send_all(status_code, headers, None)
+    }
+
+    pub fn send_json(&mut self, status_code: ResponseStatusCode, json: Option<Value>) -> ResponseResult<(), ResponseErrors> {
+        self.send_all(status_code, None, json)
+    }
+
+    pub fn json<T: Serialize>(&mut self, status_code: ResponseStatusCode, value: &T) -> ResponseResult<(), ResponseErrors> {
+        match serde_json::to_value(value) {
+            Ok(json) => self.send_json(status_code, Some(json)),
+            Err(_) => Err(ResponseErrors::ParseJson)
+        }
+    }
+
+    pub fn send_error(&mut self, err: &HandlerError) -> ResponseResult<(), ResponseErrors> {
+        self.send_json(err.status_code(), Some(err.body().clone()))
+    }
+
+    pub fn send_all(&mut self, status_code: ResponseStatusCode, headers: Option<HeaderMap>, json: Option<Value>) -> ResponseResult<(), ResponseErrors> {
+        if self.sent {
+            return Err(ResponseErrors::AlreadySent);
+        }
+        if let Some(headers) = &headers {
+            Response::check_headers(headers)?;
+        }
+
+        let outgoing = self.prepare(status_code, headers, json);
+        Response::check_headers(&outgoing.headers)?;
+
+        let parsed_string = Response::get_parsed_data(self.version,
+            Response::parse_data(self.version, self.method, outgoing.status_code, Some(outgoing.headers), outgoing.body));
+
+        self.sent = true;
+        self.write_all(parsed_string.as_bytes())
     }
 
-    pub fn send(&mut self, status_code: ResponseStatusCode) {
-        self.send_all(status_code, None, None);
+    pub fn start_chunked(&mut self, status_code: ResponseStatusCode, headers: Option<HeaderMap>) -> ResponseResult<ChunkedWriter<'_>, ResponseErrors> {
+        if self.sent {
+            return Err(ResponseErrors::AlreadySent);
+        }
+        if let Some(headers) = &headers {
+            Response::check_headers(headers)?;
+        }
+
+        let chunked = self.version != HttpVersion::HTTP_1_0;
+        if !chunked {
+            self.keep_alive = false;
+        }
+
+        let mut outgoing = self.prepare(status_code, headers, None);
+        outgoing.headers.remove("Content-Length");
+        outgoing.headers.remove("Transfer-Encoding");
+        if chunked {
+            outgoing.headers.insert("Transfer-Encoding", "chunked");
+        }
+        Response::check_headers(&outgoing.headers)?;
+
+        let head = format!("{} {}{}\r\n\r\n", self.version, outgoing.status_code, Response::parse_headers(&outgoing.headers));
+        self.sent = true;
+        self.write_all(head.as_bytes())?;
+
+        let head_only = self.method == RequestMethod::HEAD;
+        Ok(ChunkedWriter { response: self, chunked, head_only, finished: false })
     }
 
-    pub fn send_headers(&mut self, status_code: ResponseStatusCode, headers: Option<HashMap<String, String>>) {
-        self.send_all(status_code, headers, None);
+    fn check_headers(headers: &HeaderMap) -> ResponseResult<(), ResponseErrors> {
+        match headers.invalid_entry() {
+            Some(name) => Err(ResponseErrors::InvalidHeader { name: format!("{:?}", name) }),
+            None => Ok(())
+        }
     }
 
-    pub fn send_json(&mut self, status_code: ResponseStatusCode, json: Option<Value>) {
-        self.send_all(status_code, None, json);
+    fn write_all(&mut self, data: &[u8]) -> ResponseResult<(), ResponseErrors> {
+        match self.stream.write_all(data).and_then(|_| self.stream.flush()) {
+            Ok(()) => Ok(()),
+            Err(source) => {
+                self.keep_alive = false;
+                Err(ResponseErrors::Io { source })
+            }
+        }
     }
 
-    pub fn send_all(&mut self, status_code: ResponseStatusCode, headers: Option<HashMap<String, String>>, json: Option<Value>) {
-        let parsed_string = Response::get_parsed_data(Response::parse_data(status_code, headers, json));
+    fn prepare(&mut self, status_code: ResponseStatusCode, headers: Option<HeaderMap>, json: Option<Value>) -> OutgoingResponse {
+        let mut outgoing = OutgoingResponse {
+            status_code,
+            headers: headers.unwrap_or_default(),
+            body: json,
+        };
+
+        for cookie in std::mem::take(&mut self.cookies) {
+            outgoing.headers.append("Set-Cookie", &cookie.to_string());
+        }
+
+        let hooks = std::mem::take(&mut self.hooks);
+        for hook in hooks.into_iter().rev() {
+            hook(&mut outgoing);
+        }
+
+        let connection_close = outgoing.headers.get_all("Connection").iter()
+            .any(|value| value.eq_ignore_ascii_case("close"));
+        if connection_close {
+            self.keep_alive = false;
+        } else if !self.keep_alive {
+            outgoing.headers.insert("Connection", "close");
+        } else if self.version == HttpVersion::HTTP_1_0 {
+            outgoing.headers.insert("Connection", "keep-alive");
+        }
 
-        self.stream.write(parsed_string.as_bytes()).unwrap();
-        self.stream.flush().unwrap();
+        outgoing
     }
 
-    fn get_parsed_data(parsed_or_fail: ResponseResult<String, ResponseErrors>) -> String {
+    fn get_parsed_data(version: HttpVersion, parsed_or_fail: ResponseResult<String, ResponseErrors>) -> String {
         match parsed_or_fail {
             Ok(value) => value,
-            Err(_) => String::from("HTTP/1.1 500 INTERNAL SERVER ERROR\r\n")
+            Err(_) => format!("{} 500 INTERNAL SERVER ERROR\r\nContent-Length: 0\r\n\r\n", version)
         }
     }
 
-    fn parse_data(status_code: ResponseStatusCode, headers: Option<HashMap<String, String>>, json: Option<Value>) -> ResponseResult<String, ResponseErrors> {
-        let headers = match headers {
-            Some(h) => format!("{}", Response::parse_headers(h)),
-            None => String::from("")
-        };
+    fn parse_data(version: HttpVersion, method: RequestMethod, status_code: ResponseStatusCode, headers: Option<HeaderMap>, json: Option<Value>) -> ResponseResult<String, ResponseErrors> {
+        let mut headers = headers.unwrap_or_default();
+        headers.remove("Content-Length");
+        let has_content_type = headers.contains_key("Content-Type");
+        let headers = Response::parse_headers(&headers);
 
         let body: String = match json {
+            _ if !status_code.allows_body() => String::from("\r\n\r\n"),
             Some(value) => {
                 let serde_result: serde_result<String> = serde_json::to_string(&value);
                 let text: String = match serde_result {
@@ -57,20 +214,26 @@ impl Response {
                     }
                 };
 
-                let mut json_headers: HashMap<String, String> = HashMap::new();
+                let mut json_headers = HeaderMap::new();
 
-                json_headers.insert(String::from("Content-Type"), String::from("application/json"));
-                json_headers.insert(String::from("Content-Length"), text.as_bytes().len().to_string());
+                if !has_content_type {
+                    json_headers.insert("Content-Type", "application/json");
+                }
+                json_headers.insert("Content-Length", &text.len().to_string());
 
-                format!("{}\r\n\r\n{}\r\n", Response::parse_headers(json_headers), text)
+                if method == RequestMethod::HEAD {
+                    format!("{}\r\n\r\n", Response::parse_headers(&json_headers))
+                } else {
+                    format!("{}\r\n\r\n{}", Response::parse_headers(&json_headers), text)
+                }
             }
-            None => String::from("\r\n\r\n")
+            None => String::from("\r\nContent-Length: 0\r\n\r\n")
         };
 
-        Ok(format!("HTTP/1.1 {}{}{}", status_code.to_string(), headers, body))
+        Ok(format!("{} {}{}{}", version, status_code, headers, body))
     }
 
-    fn parse_headers(headers: HashMap<String, String>) -> String {
+    fn parse_headers(headers: &HeaderMap) -> String {
         let mut to_return = String::new();
         for (key, value) in headers.iter() {
             to_return.push_str(&format!("\r\n{}: {}", key, value)[..]);
@@ -79,28 +242,101 @@ impl Response {
     }
 }
 
+pub struct ChunkedWriter<'a> {
+    response: &'a mut Response,
+    chunked: bool,
+    head_only: bool,
+    finished: bool,
+}
+
+impl ChunkedWriter<'_> {
+    pub fn write_chunk(&mut self, data: &[u8]) -> ResponseResult<(), ResponseErrors> {
+        match self.write_data(data) {
+            Ok(()) => Ok(()),
+            Err(source) => Err(ResponseErrors::Io { source })
+        }
+    }
+
+    pub fn finish(mut self, trailers: Option<HeaderMap>) -> ResponseResult<(), ResponseErrors> {
+        if !self.chunked || self.head_only {
+            self.response.write_all(&[])?;
+            self.finished = true;
+            return Ok(());
+        }
+
+        let trailers = match trailers {
+            Some(t) => {
+                Response::check_headers(&t)?;
+                Response::parse_headers(&t)
+            }
+            None => String::from("")
+        };
+
+        self.response.write_all(format!("0{}\r\n\r\n", trailers).as_bytes())?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if data.is_empty() || self.head_only {
+            return Ok(());
+        }
+
+        let stream = &mut self.response.stream;
+        if !self.chunked {
+            stream.write_all(data)?;
+            return stream.flush();
+        }
+
+        stream.write_all(format!("{:X}\r\n", data.len()).as_bytes())?;
+        stream.write_all(data)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()
+    }
+}
+
+impl Write for ChunkedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_data(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.response.stream.flush()
+    }
+}
+
+impl Drop for ChunkedWriter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.response.keep_alive = false;
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::server::test_support::connected_response;
 
     #[test]
     fn send_without_body_without_header() {
-        let to_get = Response::parse_data(ResponseStatusCode::OK, None, None);
-        let result = Response::get_parsed_data(to_get);
-        assert_eq!("HTTP/1.1 200 OK\r\n\r\n", result)
+        let to_get = Response::parse_data(HttpVersion::HTTP_1_1, RequestMethod::GET, ResponseStatusCode::OK, None, None);
+        let result = Response::get_parsed_data(HttpVersion::HTTP_1_1, to_get);
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n", result)
     }
 
     #[test]
     fn send_without_body_with_header() {
-        let mut headers: HashMap<String, String> = HashMap::new();
-        headers.insert(String::from("Accept"), String::from("*/*"));
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept", "*/*");
 
         let to_get =
-            Response::parse_data(ResponseStatusCode::NOT_FOUND,
+            Response::parse_data(HttpVersion::HTTP_1_1, RequestMethod::GET, ResponseStatusCode::NOT_FOUND,
                                  Some(headers), None);
-        let result = Response::get_parsed_data(to_get);
+        let result = Response::get_parsed_data(HttpVersion::HTTP_1_1, to_get);
 
-        assert_eq!("HTTP/1.1 404 NOT FOUND\r\nAccept: */*\r\n\r\n", result)
+        assert_eq!("HTTP/1.1 404 NOT FOUND\r\nAccept: */*\r\nContent-Length: 0\r\n\r\n", result)
     }
 
     #[test]
@@ -108,11 +344,214 @@ mod test {
         let json = serde_json::from_str("{\"id\":1,\"name\":\"Vand\",\"password\":\"123\"}").unwrap();
 
         let to_get =
-            Response::parse_data(ResponseStatusCode::ACCEPTED,
+            Response::parse_data(HttpVersion::HTTP_1_1, RequestMethod::GET, ResponseStatusCode::ACCEPTED,
                                  None ,Some(json) );
-        let result = Response::get_parsed_data(to_get);
-        let one = "HTTP/1.1 202 ACCEPTED\r\nContent-Type: application/json\r\nContent-Length: 39\r\n\r\n{\"id\":1,\"name\":\"Vand\",\"password\":\"123\"}\r\n";
-        let two = "HTTP/1.1 202 ACCEPTED\r\nContent-Length: 39\r\nContent-Type: application/json\r\n\r\n{\"id\":1,\"name\":\"Vand\",\"password\":\"123\"}\r\n";
-        assert!(one.eq(result.as_str()) || two.eq(result.as_str()))
+        let result = Response::get_parsed_data(HttpVersion::HTTP_1_1, to_get);
+        assert_eq!("HTTP/1.1 202 ACCEPTED\r\nContent-Type: application/json\r\nContent-Length: 39\r\n\r\n{\"id\":1,\"name\":\"Vand\",\"password\":\"123\"}", result)
+    }
+
+    #[test]
+    fn send_with_body_keeps_content_type() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "application/problem+json");
+
+        let to_get = Response::parse_data(HttpVersion::HTTP_1_1, RequestMethod::GET, ResponseStatusCode::BAD_REQUEST, Some(headers),
+                                          Some(serde_json::json!({ "title": "bad" })));
+        let result = Response::get_parsed_data(HttpVersion::HTTP_1_1, to_get);
+        assert_eq!("HTTP/1.1 400 BAD REQUEST\r\nContent-Type: application/problem+json\r\nContent-Length: 15\r\n\r\n{\"title\":\"bad\"}", result)
+    }
+
+    #[test]
+    fn head_responses_have_no_body() {
+        let to_get = Response::parse_data(HttpVersion::HTTP_1_1, RequestMethod::HEAD, ResponseStatusCode::OK, None,
+                                          Some(serde_json::json!({ "a": 1 })));
+        let result = Response::get_parsed_data(HttpVersion::HTTP_1_1, to_get);
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n", result);
+
+        let (mut response, mut client) = connected_response();
+        response.set_method(RequestMethod::HEAD);
+        response.set_keep_alive(true);
+        let mut writer = response.start_chunked(ResponseStatusCode::OK, None).unwrap();
+        writer.write_chunk(b"ignored").unwrap();
+        writer.finish(None).unwrap();
+        assert!(response.keep_alive());
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+    }
+
+    #[test]
+    fn send_chunked() {
+        let (mut response, mut client) = connected_response();
+        response.set_keep_alive(true);
+
+        let mut writer = response.start_chunked(ResponseStatusCode::OK, None).unwrap();
+        writer.write_chunk(b"hello, ").unwrap();
+        writer.write_chunk(b"").unwrap();
+        writer.write_all(b"chunked world").unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("X-Rows", "2");
+        writer.finish(Some(trailers)).unwrap();
+
+        assert!(response.is_sent());
+        assert!(response.keep_alive());
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                              7\r\nhello, \r\nD\r\nchunked world\r\n0\r\nX-Rows: 2\r\n\r\n");
+    }
+
+    #[test]
+    fn unfinished_chunked_closes_connection() {
+        let (mut response, _client) = connected_response();
+        response.set_keep_alive(true);
+
+        let mut writer = response.start_chunked(ResponseStatusCode::OK, None).unwrap();
+        writer.write_chunk(b"partial").unwrap();
+        drop(writer);
+
+        assert!(!response.keep_alive());
+    }
+
+    #[test]
+    fn http_1_0_response() {
+        let (mut response, mut client) = connected_response();
+        response.set_version(HttpVersion::HTTP_1_0);
+        response.set_keep_alive(true);
+        response.send(ResponseStatusCode::OK).unwrap();
+        assert!(response.keep_alive());
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn http_1_0_streams_without_chunking() {
+        let (mut response, mut client) = connected_response();
+        response.set_version(HttpVersion::HTTP_1_0);
+        response.set_keep_alive(true);
+
+        let mut writer = response.start_chunked(ResponseStatusCode::OK, None).unwrap();
+        writer.write_chunk(b"hello").unwrap();
+        writer.finish(None).unwrap();
+        assert!(!response.keep_alive());
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nhello");
+    }
+
+    #[test]
+    fn send_to_closed_peer_returns_error() {
+        let (mut response, client) = connected_response();
+        response.set_keep_alive(true);
+        drop(client);
+
+        let body = Some(serde_json::json!({ "data": "x".repeat(32 * 1024 * 1024) }));
+        let result = response.send_json(ResponseStatusCode::OK, body);
+
+        assert!(matches!(result, Err(ResponseErrors::Io { .. })));
+        assert!(response.is_sent());
+        assert!(!response.keep_alive());
+    }
+
+    #[test]
+    fn send_twice_is_rejected() {
+        let (mut response, mut client) = connected_response();
+        response.set_keep_alive(true);
+        response.send(ResponseStatusCode::OK).unwrap();
+
+        assert!(matches!(response.send(ResponseStatusCode::CREATED), Err(ResponseErrors::AlreadySent)));
+        assert!(matches!(response.start_chunked(ResponseStatusCode::OK, None), Err(ResponseErrors::AlreadySent)));
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn failed_finish_closes_connection() {
+        let (mut response, client) = connected_response();
+        response.set_keep_alive(true);
+        drop(client);
+
+        let mut writer = response.start_chunked(ResponseStatusCode::OK, None).unwrap();
+        let _ = writer.write_chunk(&vec![b'x'; 32 * 1024 * 1024]);
+        assert!(writer.finish(None).is_err());
+        assert!(!response.keep_alive());
+    }
+
+    #[test]
+    fn reject_header_injection() {
+        let (mut response, mut client) = connected_response();
+        response.set_cookie(Cookie::new("theme", "dark")).unwrap();
+        response.on_send(|outgoing| outgoing.headers.insert("X-Hook", "ran"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Echo", "a\r\nSet-Cookie: evil=1");
+        assert!(matches!(response.send_headers(ResponseStatusCode::OK, Some(headers)), Err(ResponseErrors::InvalidHeader { .. })));
+        assert!(!response.is_sent());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Bad:\r\nName", "value");
+        assert!(matches!(response.start_chunked(ResponseStatusCode::OK, Some(headers)), Err(ResponseErrors::InvalidHeader { .. })));
+
+        response.send(ResponseStatusCode::BAD_REQUEST).unwrap();
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 400 BAD REQUEST\r\nSet-Cookie: theme=dark\r\nX-Hook: ran\r\nConnection: close\r\n\
+                              Content-Length: 0\r\n\r\n");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn send_repeated_headers_in_order() {
+        let (mut response, mut client) = connected_response();
+
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Link", "</style.css>; rel=preload");
+        headers.append("Set-Cookie", "b=2");
+        response.send_headers(ResponseStatusCode::NO_CONTENT, Some(headers)).unwrap();
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 204 NO CONTENT\r\nSet-Cookie: a=1\r\nLink: </style.css>; rel=preload\r\n\
+                              Set-Cookie: b=2\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn bodyless_statuses_omit_content_length() {
+        let to_get = Response::parse_data(HttpVersion::HTTP_1_1, RequestMethod::GET, ResponseStatusCode::NOT_MODIFIED, None,
+                                          Some(serde_json::json!({ "ignored": true })));
+        let result = Response::get_parsed_data(HttpVersion::HTTP_1_1, to_get);
+        assert_eq!("HTTP/1.1 304 NOT MODIFIED\r\n\r\n", result);
+    }
+
+    #[test]
+    fn send_cookies() {
+        let (mut response, mut client) = connected_response();
+        response.set_keep_alive(true);
+        response.set_cookie(Cookie::new("theme", "dark").path("/")).unwrap();
+        response.set_cookie(Cookie::removal("session")).unwrap();
+        assert!(matches!(response.set_cookie(Cookie::new("pref", "x; Domain=evil.com")), Err(ResponseErrors::InvalidCookie { .. })));
+        response.send(ResponseStatusCode::OK).unwrap();
+        drop(response);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 200 OK\r\nSet-Cookie: theme=dark; Path=/\r\n\
+                              Set-Cookie: session=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT\r\n\
+                              Content-Length: 0\r\n\r\n");
+    }
+}
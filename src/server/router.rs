@@ -0,0 +1,500 @@
+use crate::http_enums::{RequestMethod, ResponseStatusCode};
+use crate::request::Request;
+use crate::response::Response;
+use crate::tcp_server::{Handler, HandlerOutcome};
+use crate::server_errors::{HandlerError, HandlerResult};
+use crate::middleware::{Middleware, MiddlewareFlow};
+use std::collections::HashMap;
+use crate::header_map::HeaderMap;
+use std::sync::Arc;
+use percent_encoding::percent_decode_str;
+
+type RouteHandler = Box<dyn Fn(Request, &mut Response) -> HandlerResult + Send + Sync + 'static>;
+
+#[derive(Debug,PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+#[derive(Debug)]
+struct PathPattern {
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    fn compile(pattern: &str) -> PathPattern {
+        let parts: Vec<&str> = split_path(pattern);
+        let mut segments: Vec<Segment> = Vec::new();
+
+        for (index, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                if name.is_empty() {
+                    panic!("Route {} has a parameter without a name", pattern);
+                }
+                Segment::Param(String::from(name))
+            } else if let Some(name) = part.strip_prefix('*') {
+                if name.is_empty() {
+                    panic!("Route {} has a wildcard without a name", pattern);
+                }
+                if index != parts.len() - 1 {
+                    panic!("Route {} has a wildcard that is not the last segment", pattern);
+                }
+                Segment::Wildcard(String::from(name))
+            } else {
+                Segment::Static(String::from(*part))
+            };
+            segments.push(segment);
+        }
+
+        PathPattern { segments }
+    }
+
+    fn captures(&self, path: &str) -> Option<HashMap<String, String>> {
+        let parts: Vec<&str> = split_path(path);
+        if parts.iter().any(|part| is_dot_segment(part)) {
+            return None;
+        }
+        let mut params: HashMap<String, String> = HashMap::new();
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(expected) => {
+                    if parts.get(index) != Some(&expected.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = parts.get(index)?;
+                    params.insert(name.clone(), decode_segment(value));
+                }
+                Segment::Wildcard(name) => {
+                    params.insert(name.clone(), parts[index..].join("/"));
+                    return Some(params);
+                }
+            }
+        }
+
+        if parts.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
+}
+
+fn decode_segment(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().to_string()
+}
+
+fn is_dot_segment(segment: &str) -> bool {
+    let decoded = percent_decode_str(segment).decode_utf8_lossy();
+    decoded == "." || decoded == ".."
+}
+
+struct Route {
+    method: RequestMethod,
+    pattern: PathPattern,
+    handler: RouteHandler,
+    body_limit: Option<usize>,
+    stream_body: bool,
+}
+
+enum RouteMatch<'a> {
+    Found(&'a Route, HashMap<String, String>),
+    MethodNotAllowed(Vec<RequestMethod>),
+    NotFound,
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new(), middleware: Vec::new() }
+    }
+
+    pub fn middleware<M: Middleware>(&mut self, middleware: M) -> &mut Router {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn add<T, R>(&mut self, method: RequestMethod, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: PathPattern::compile(path),
+            handler: Box::new(move |request, response| handler(request, response).into_result()),
+            body_limit: None,
+            stream_body: false,
+        });
+        self
+    }
+
+    pub fn body_limit(&mut self, max_body_size: usize) -> &mut Router {
+        match self.routes.last_mut() {
+            Some(route) => route.body_limit = Some(max_body_size),
+            None => panic!("body_limit must be called after registering a route")
+        }
+        self
+    }
+
+    pub fn stream_body(&mut self) -> &mut Router {
+        match self.routes.last_mut() {
+            Some(route) => route.stream_body = true,
+            None => panic!("stream_body must be called after registering a route")
+        }
+        self
+    }
+
+    pub fn get<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::GET, path, handler)
+    }
+
+    pub fn post<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::POST, path, handler)
+    }
+
+    pub fn put<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::PUT, path, handler)
+    }
+
+    pub fn patch<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::PATCH, path, handler)
+    }
+
+    pub fn delete<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::DELETE, path, handler)
+    }
+
+    pub fn head<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::HEAD, path, handler)
+    }
+
+    pub fn options<T, R>(&mut self, path: &str, handler: T) -> &mut Router
+        where
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
+    {
+        self.add(RequestMethod::OPTIONS, path, handler)
+    }
+
+    pub fn dispatch(&self, mut request: Request, response: &mut Response) -> HandlerResult {
+        for middleware in self.middleware.iter() {
+            if middleware.before(&mut request, response) == MiddlewareFlow::Stop {
+                if response.is_sent() {
+                    return Ok(());
+                }
+                return Err(HandlerError::new(ResponseStatusCode::INTERNAL_SERVER_ERROR, "Middleware stopped the request without a response"));
+            }
+            let middleware = Arc::clone(middleware);
+            response.on_send(move |outgoing| middleware.after(outgoing));
+        }
+
+        match self.find(request.method(), request.path()) {
+            RouteMatch::Found(route, params) => {
+                request.set_params(params);
+                return (route.handler)(request, response);
+            }
+            RouteMatch::MethodNotAllowed(allowed) => {
+                let allowed: Vec<String> = allowed.iter().map(|method| method.to_string()).collect();
+                let mut headers = HeaderMap::new();
+                headers.insert("Allow", &allowed.join(", "));
+                response.send_headers(ResponseStatusCode::METHOD_NOT_ALLOWED, Some(headers))?;
+            }
+            RouteMatch::NotFound => response.send(ResponseStatusCode::NOT_FOUND)?,
+        }
+        Ok(())
+    }
+
+    fn find(&self, method: &RequestMethod, path: &str) -> RouteMatch<'_> {
+        let mut allowed: Vec<RequestMethod> = Vec::new();
+
+        for route in self.routes.iter() {
+            let params = match route.pattern.captures(path) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == *method {
+                return RouteMatch::Found(route, params);
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            RouteMatch::NotFound
+        } else {
+            RouteMatch::MethodNotAllowed(allowed)
+        }
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: Request, response: &mut Response) -> HandlerResult {
+        self.dispatch(request, response)
+    }
+
+    fn body_limit_for(&self, request: &Request) -> Option<usize> {
+        match self.find(request.method(), request.path()) {
+            RouteMatch::Found(route, _) => route.body_limit,
+            _ => None
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        match self.find(request.method(), request.path()) {
+            RouteMatch::Found(route, _) => route.stream_body,
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::response::OutgoingResponse;
+    use crate::server::test_support::exchange;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.get("/users", |_req, res| Ok(res.send(ResponseStatusCode::OK)?))
+            .post("/users", |_req, res| Ok(res.send(ResponseStatusCode::CREATED)?))
+            .get("/health", |_req, res| Ok(res.send(ResponseStatusCode::NO_CONTENT)?))
+            .get("/users/:id/orders/:order_id", |_req, res| Ok(res.send(ResponseStatusCode::OK)?))
+            .get("/static/*rest", |_req, res| Ok(res.send(ResponseStatusCode::OK)?));
+        router
+    }
+
+    #[test]
+    fn find_registered_route() {
+        let router = router();
+        match router.find(&RequestMethod::POST, "/users") {
+            RouteMatch::Found(route, params) => {
+                assert_eq!(route.method, RequestMethod::POST);
+                assert_eq!(route.pattern.segments, vec![Segment::Static(String::from("users"))]);
+                assert!(params.is_empty());
+            }
+            _ => panic!("Expected /users to match"),
+        }
+    }
+
+    #[test]
+    fn find_unknown_path() {
+        let router = router();
+        assert!(matches!(router.find(&RequestMethod::GET, "/orders"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn find_wrong_method() {
+        let router = router();
+        match router.find(&RequestMethod::DELETE, "/users") {
+            RouteMatch::MethodNotAllowed(allowed) => {
+                assert_eq!(allowed, vec![RequestMethod::GET, RequestMethod::POST]);
+            }
+            _ => panic!("Expected /users to reject DELETE"),
+        }
+    }
+
+    #[test]
+    fn find_named_params() {
+        let router = router();
+        match router.find(&RequestMethod::GET, "/users/42/orders/a%20b") {
+            RouteMatch::Found(route, params) => {
+                assert_eq!(route.pattern.segments.len(), 4);
+                assert_eq!(params.get("id").unwrap(), "42");
+                assert_eq!(params.get("order_id").unwrap(), "a b");
+            }
+            _ => panic!("Expected /users/42/orders/a%20b to match"),
+        }
+        match router.find(&RequestMethod::GET, "/users/a%2Fb/orders/100%25") {
+            RouteMatch::Found(_, params) => {
+                assert_eq!(params.get("id").unwrap(), "a/b");
+                assert_eq!(params.get("order_id").unwrap(), "100%");
+            }
+            _ => panic!("Expected /users/a%2Fb/orders/100%25 to match"),
+        }
+        assert!(matches!(router.find(&RequestMethod::GET, "/users/42/orders"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn find_wildcard() {
+        let router = router();
+        match router.find(&RequestMethod::GET, "/static/css/site.css") {
+            RouteMatch::Found(_, params) => assert_eq!(params.get("rest").unwrap(), "css/site.css"),
+            _ => panic!("Expected /static/css/site.css to match"),
+        }
+        match router.find(&RequestMethod::GET, "/static/a%2Fb%20c.css") {
+            RouteMatch::Found(_, params) => assert_eq!(params.get("rest").unwrap(), "a%2Fb%20c.css"),
+            _ => panic!("Expected /static/a%2Fb%20c.css to match"),
+        }
+        assert!(matches!(router.find(&RequestMethod::GET, "/static/..%2F..%2Fetc%2Fpasswd"), RouteMatch::Found(_, ref params)
+            if params.get("rest").unwrap() == "..%2F..%2Fetc%2Fpasswd"));
+        assert!(matches!(router.find(&RequestMethod::GET, "/static/css/../../etc/passwd"), RouteMatch::NotFound));
+        assert!(matches!(router.find(&RequestMethod::GET, "/static/%2e%2e/secret"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wildcard_must_be_last() {
+        PathPattern::compile("/static/*rest/more");
+    }
+
+    fn roundtrip(router: &Router, raw_request: &str) -> String {
+        exchange(|response| {
+            if let Err(err) = router.dispatch(Request::from_str(raw_request).unwrap(), response) {
+                response.send_error(&err).unwrap();
+            }
+        })
+    }
+
+    struct RequireToken;
+
+    impl Middleware for RequireToken {
+        fn before(&self, request: &mut Request, response: &mut Response) -> MiddlewareFlow {
+            if request.headers().contains_key("Authorization") {
+                MiddlewareFlow::Next
+            } else {
+                let _ = response.send(ResponseStatusCode::UNAUTHORIZED);
+                MiddlewareFlow::Stop
+            }
+        }
+    }
+
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn after(&self, response: &mut OutgoingResponse) {
+            let tags = match response.headers.get("X-Tags") {
+                Some(tags) => format!("{},{}", tags, self.0),
+                None => String::from(self.0)
+            };
+            response.headers.insert("X-Tags", &tags);
+        }
+    }
+
+    #[test]
+    fn body_limit_per_route() {
+        let mut router = router();
+        router.post("/upload", |_req, res| Ok(res.send(ResponseStatusCode::CREATED)?))
+            .body_limit(10 * 1024 * 1024)
+            .stream_body();
+
+        let upload = Request::from_str("POST /upload HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(router.body_limit_for(&upload), Some(10 * 1024 * 1024));
+        assert!(router.streams_body(&upload));
+        let users = Request::from_str("POST /users HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(router.body_limit_for(&users), None);
+        assert!(!router.streams_body(&users));
+    }
+
+    #[test]
+    fn handler_errors_become_json_responses() {
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct User {
+            name: String,
+            age: u8,
+        }
+
+        let mut router = Router::new();
+        router.post("/users", |req, res| {
+            let user: User = req.json()?;
+            res.json(ResponseStatusCode::CREATED, &user)?;
+            Ok(())
+        });
+
+        let received = roundtrip(&router, "POST /users HTTP/1.1\r\n\r\n{\"name\":\"Vand\",\"age\":30}");
+        assert!(received.starts_with("HTTP/1.1 201 CREATED"));
+        assert!(received.ends_with("{\"age\":30,\"name\":\"Vand\"}"));
+
+        let received = roundtrip(&router, "POST /users HTTP/1.1\r\n\r\n{\"name\":\"Vand\",\"age\":300}");
+        assert!(received.starts_with("HTTP/1.1 422 UNPROCESSABLE ENTITY"));
+        let body: serde_json::Value = serde_json::from_str(received.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["path"], "age");
+
+        let received = roundtrip(&router, "POST /users HTTP/1.1\r\n\r\n{\"name\":");
+        assert!(received.starts_with("HTTP/1.1 400 BAD REQUEST"));
+    }
+
+    #[test]
+    fn dispatch_not_found() {
+        let received = roundtrip(&router(), "GET /orders HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 404 NOT FOUND"));
+    }
+
+    #[test]
+    fn middleware_short_circuits() {
+        let mut router = router();
+        router.middleware(Tag("outer")).middleware(RequireToken);
+
+        let received = roundtrip(&router, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 401 UNAUTHORIZED"));
+        assert!(received.contains("X-Tags: outer\r\n"));
+
+        let received = roundtrip(&router, "GET /users HTTP/1.1\r\nAuthorization: token\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn middleware_stop_without_response_is_an_error() {
+        struct Silent;
+
+        impl Middleware for Silent {
+            fn before(&self, _request: &mut Request, _response: &mut Response) -> MiddlewareFlow {
+                MiddlewareFlow::Stop
+            }
+        }
+
+        let mut router = router();
+        router.middleware(Silent);
+
+        let received = roundtrip(&router, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 500 INTERNAL SERVER ERROR"));
+    }
+
+    #[test]
+    fn middleware_after_runs_in_reverse_order() {
+        let mut router = router();
+        router.middleware(Tag("outer")).middleware(Tag("inner"));
+
+        let received = roundtrip(&router, "GET /health HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 204 NO CONTENT"));
+        assert!(received.contains("X-Tags: inner,outer\r\n"));
+    }
+}
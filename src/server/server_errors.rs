@@ -1,24 +1,152 @@
 extern crate custom_error;
 
 use custom_error::custom_error;
+use crate::http_enums::ResponseStatusCode;
+use serde_json::{json, Value};
+use std::fmt;
 
 custom_error! {#[derive(PartialEq,PartialOrd)] pub RequestErrors
     HTTPHeader { request: String } = "Invalid request header: {request}",
     HTTPRequest { method: String } = "Invalid method: {method}",
+    HTTPVersion { version: String } = "Unsupported HTTP version: {version}",
     UnparsedRequest { request: String } = "Invalid request: {request}",
-    ParseJson { json: String } = "Json with non empty body: {json}"
+    ParseJson { json: String } = "Json with non empty body: {json}",
+    JsonField { path: String, reason: String } = "Invalid json at {path}: {reason}",
+    MissingParam { name: String } = "Missing path parameter: {name}",
+    InvalidParam { name: String, value: String } = "Invalid value for path parameter {name}: {value}",
+    ParseQuery { query: String, reason: String } = "Invalid query string {query}: {reason}",
+    ContentLength { length: String } = "Invalid Content-Length: {length}",
+    MessageFraming { reason: String } = "Ambiguous message framing: {reason}",
+    IncompleteHeader { received: usize } = "Connection closed after {received} bytes of request header",
+    IncompleteBody { expected: usize, received: usize } = "Expected {expected} bytes of body but received {received}",
+    IncompleteChunkedBody { received: usize } = "Connection closed after {received} bytes of chunked body",
+    RequestTimeout { part: String } = "Timed out reading the request {part}",
+    ChunkedEncoding { reason: String } = "Invalid chunked encoding: {reason}",
+    TransferEncoding { encoding: String } = "Unsupported Transfer-Encoding: {encoding}",
+    UriTooLong { length: usize, limit: usize } = "Request target of {length} bytes exceeds the limit of {limit}",
+    HeaderFieldsTooLarge { reason: String } = "Request header too large: {reason}",
+    PayloadTooLarge { size: usize, limit: usize } = "Request body of {size} bytes exceeds the limit of {limit}",
+    ContentType { content_type: String } = "Unexpected Content-Type: {content_type}",
+    UnsupportedCharset { charset: String } = "Unsupported charset: {charset}",
+    InvalidText { charset: String } = "Body is not valid {charset} text",
+    ParseForm { reason: String } = "Invalid form body: {reason}",
+    Multipart { reason: String } = "Invalid multipart body: {reason}",
+    TempFile { reason: String } = "Could not store multipart part: {reason}"
+}
+
+impl RequestErrors {
+    pub fn status_code(&self) -> ResponseStatusCode {
+        match self {
+            RequestErrors::HTTPHeader { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::HTTPRequest { .. } => ResponseStatusCode::NOT_IMPLEMENTED,
+            RequestErrors::HTTPVersion { .. } => ResponseStatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            RequestErrors::UnparsedRequest { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::ParseJson { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::JsonField { .. } => ResponseStatusCode::UNPROCESSABLE_ENTITY,
+            RequestErrors::MissingParam { .. } => ResponseStatusCode::INTERNAL_SERVER_ERROR,
+            RequestErrors::InvalidParam { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::ParseQuery { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::ContentLength { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::MessageFraming { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::IncompleteHeader { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::IncompleteBody { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::IncompleteChunkedBody { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::RequestTimeout { .. } => ResponseStatusCode::REQUEST_TIMEOUT,
+            RequestErrors::ChunkedEncoding { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::TransferEncoding { .. } => ResponseStatusCode::NOT_IMPLEMENTED,
+            RequestErrors::UriTooLong { .. } => ResponseStatusCode::URI_TOO_LONG,
+            RequestErrors::HeaderFieldsTooLarge { .. } => ResponseStatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            RequestErrors::PayloadTooLarge { .. } => ResponseStatusCode::PAYLOAD_TOO_LARGE,
+            RequestErrors::ContentType { .. } => ResponseStatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RequestErrors::UnsupportedCharset { .. } => ResponseStatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RequestErrors::InvalidText { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::ParseForm { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::Multipart { .. } => ResponseStatusCode::BAD_REQUEST,
+            RequestErrors::TempFile { .. } => ResponseStatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 pub type RequestResult<T, E = RequestErrors> = std::result::Result<T, E>;
 
 custom_error! {pub ResponseErrors
-    ParseJson = "Error parsing json."
+    ParseJson = "Error parsing json.",
+    AlreadySent = "A response was already sent for this request",
+    InvalidHeader { name: String } = "Invalid response header: {name}",
+    InvalidCookie { name: String } = "Invalid cookie: {name}",
+    Io { source: std::io::Error } = "Error writing the response: {source}"
 }
 
 pub type ResponseResult<T, E = ResponseErrors> = std::result::Result<T, E>;
 
+#[derive(Debug)]
+pub struct HandlerError {
+    status_code: ResponseStatusCode,
+    body: Value,
+}
+
+impl HandlerError {
+    pub fn new(status_code: ResponseStatusCode, message: &str) -> HandlerError {
+        HandlerError { status_code, body: json!({ "error": message }) }
+    }
+
+    pub fn status_code(&self) -> ResponseStatusCode {
+        self.status_code
+    }
+
+    pub fn body(&self) -> &Value {
+        &self.body
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.status_code, self.body)
+    }
+}
+
+impl From<RequestErrors> for HandlerError {
+    fn from(err: RequestErrors) -> HandlerError {
+        HandlerError::from(&err)
+    }
+}
+
+impl From<&RequestErrors> for HandlerError {
+    fn from(err: &RequestErrors) -> HandlerError {
+        let status_code = err.status_code();
+        match err {
+            RequestErrors::JsonField { path, reason } => {
+                HandlerError { status_code, body: json!({ "error": reason, "path": path }) }
+            }
+            _ => HandlerError::new(status_code, &err.to_string())
+        }
+    }
+}
+
+impl From<ResponseErrors> for HandlerError {
+    fn from(err: ResponseErrors) -> HandlerError {
+        HandlerError::new(ResponseStatusCode::INTERNAL_SERVER_ERROR, &err.to_string())
+    }
+}
+
+impl From<SessionErrors> for HandlerError {
+    fn from(err: SessionErrors) -> HandlerError {
+        HandlerError::new(ResponseStatusCode::INTERNAL_SERVER_ERROR, &err.to_string())
+    }
+}
+
+pub type HandlerResult<T = (), E = HandlerError> = std::result::Result<T, E>;
+
+custom_error! {pub SessionErrors
+    Serialize { reason: String } = "Could not serialize session value: {reason}",
+    InvalidId { id: String } = "Invalid session id: {id}",
+    Store { source: std::io::Error } = "Session store failed: {source}"
+}
+
+pub type SessionResult<T, E = SessionErrors> = std::result::Result<T, E>;
+
 custom_error! {pub ServerErrors
-    FailedToStart = "Error starting the server."
+    FailedToStart { source: std::io::Error } = "Error starting the server: {source}"
 }
 
 pub type ServerResult<T, E = ServerErrors> = std::result::Result<T, E>;
\ No newline at end of file
@@ -0,0 +1,576 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use crate::cookie::{Cookie, CookieKey, SameSite};
+use crate::middleware::{Middleware, MiddlewareFlow};
+use crate::request::Request;
+use crate::response::Response;
+use crate::server_errors::{SessionErrors, SessionResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_COOKIE_NAME: &str = "session_id";
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const SESSION_ID_BYTES: usize = 32;
+const TEMP_SUFFIX_BYTES: usize = 12;
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60);
+
+pub type SessionData = Map<String, Value>;
+
+pub trait SessionStore: Send + Sync + 'static {
+    fn load(&self, id: &str) -> SessionResult<Option<SessionData>>;
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> SessionResult<()>;
+
+    fn remove(&self, id: &str) -> SessionResult<()>;
+}
+
+#[derive(Debug)]
+struct SessionState {
+    id: String,
+    data: SessionData,
+    is_new: bool,
+    modified: bool,
+    destroyed: bool,
+}
+
+#[derive(Debug,Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn new(id: String, data: SessionData, is_new: bool) -> Session {
+        Session {
+            state: Arc::new(Mutex::new(SessionState { id, data, is_new, modified: false, destroyed: false }))
+        }
+    }
+
+    pub fn id(&self) -> String {
+        self.state.lock().unwrap().id.clone()
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.state.lock().unwrap().is_new
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.state.lock().unwrap().modified
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        let value = state.data.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.state.lock().unwrap().data.contains_key(key)
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: &T) -> SessionResult<()> {
+        let value = match serde_json::to_value(value) {
+            Ok(value) => value,
+            Err(err) => return Err(SessionErrors::Serialize { reason: err.to_string() })
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.data.get(key) != Some(&value) {
+            state.data.insert(String::from(key), value);
+            state.modified = true;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.data.remove(key);
+        if removed.is_some() {
+            state.modified = true;
+        }
+        removed
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.data.is_empty() {
+            state.data.clear();
+            state.modified = true;
+        }
+    }
+
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+pub struct SessionMiddleware {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    key: Option<CookieKey>,
+}
+
+impl SessionMiddleware {
+    pub fn new<S: SessionStore>(store: S) -> SessionMiddleware {
+        SessionMiddleware {
+            store: Arc::new(store),
+            cookie_name: String::from(DEFAULT_COOKIE_NAME),
+            ttl: DEFAULT_TTL,
+            secure: false,
+            key: None,
+        }
+    }
+
+    pub fn cookie_name(mut self, cookie_name: &str) -> SessionMiddleware {
+        if !Cookie::new(cookie_name, "").is_valid() {
+            panic!("Invalid session cookie name: {:?}", cookie_name);
+        }
+        self.cookie_name = String::from(cookie_name);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> SessionMiddleware {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> SessionMiddleware {
+        self.secure = secure;
+        self
+    }
+
+    pub fn signed(mut self, key: CookieKey) -> SessionMiddleware {
+        self.key = Some(key);
+        self
+    }
+
+    fn session_id(&self, request: &Request) -> Option<String> {
+        let cookies = request.cookies();
+        let id = match &self.key {
+            Some(key) => cookies.get_signed(&self.cookie_name, key)?,
+            None => String::from(cookies.get(&self.cookie_name)?)
+        };
+        if is_valid_id(&id) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    fn load(&self, request: &Request) -> Session {
+        if let Some(id) = self.session_id(request) {
+            match self.store.load(&id) {
+                Ok(Some(data)) => return Session::new(id, data, false),
+                Ok(None) => {}
+                Err(err) => eprintln!("Failed to load session: {}", err)
+            }
+        }
+        Session::new(generate_id(), SessionData::new(), true)
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        let value = match &self.key {
+            Some(key) => key.sign(&self.cookie_name, value),
+            None => String::from(value)
+        };
+        Cookie::new(&self.cookie_name, &value)
+            .path("/")
+            .max_age(self.ttl)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+    }
+}
+
+impl Middleware for SessionMiddleware {
+    fn before(&self, request: &mut Request, response: &mut Response) -> MiddlewareFlow {
+        let session = self.load(request);
+        request.set_session(session.clone());
+
+        let store = Arc::clone(&self.store);
+        let ttl = self.ttl;
+        let cookie = self.cookie(&session.id());
+        let removal = Cookie::removal(&self.cookie_name).path("/");
+
+        response.on_send(move |outgoing| {
+            let state = session.state.lock().unwrap();
+            if state.destroyed {
+                if !state.is_new {
+                    if let Err(err) = store.remove(&state.id) {
+                        eprintln!("Failed to remove session: {}", err);
+                    }
+                    outgoing.headers.append("Set-Cookie", &removal.to_string());
+                }
+            } else if state.modified {
+                match store.save(&state.id, &state.data, ttl) {
+                    Ok(()) => {
+                        if state.is_new {
+                            outgoing.headers.append("Set-Cookie", &cookie.to_string());
+                        }
+                    }
+                    Err(err) => eprintln!("Failed to save session: {}", err)
+                }
+            }
+        });
+
+        MiddlewareFlow::Next
+    }
+}
+
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    sweep_interval: Duration,
+    last_sweep: Mutex<Instant>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new(DEFAULT_SWEEP_INTERVAL)
+    }
+}
+
+impl MemoryStore {
+    pub fn new(sweep_interval: Duration) -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            sweep_interval,
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        self.sessions.lock().unwrap().retain(|_, (_, expires)| *expires > now);
+        *self.last_sweep.lock().unwrap() = now;
+    }
+
+    fn sweep_if_due(&self) {
+        let due = self.last_sweep.lock().unwrap().elapsed() >= self.sweep_interval;
+        if due {
+            self.sweep();
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> SessionResult<Option<SessionData>> {
+        self.sweep_if_due();
+        let sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some((data, expires)) if *expires > Instant::now() => Ok(Some(data.clone())),
+            _ => Ok(None)
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> SessionResult<()> {
+        self.sweep_if_due();
+        self.sessions.lock().unwrap().insert(String::from(id), (data.clone(), Instant::now() + ttl));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> SessionResult<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: AsRef<Path>>(directory: P) -> SessionResult<FileStore> {
+        let directory = directory.as_ref().to_path_buf();
+        if let Err(source) = fs::create_dir_all(&directory) {
+            return Err(SessionErrors::Store { source });
+        }
+        Ok(FileStore { directory })
+    }
+
+    pub fn sweep(&self) -> SessionResult<()> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(source) => return Err(SessionErrors::Store { source })
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let stale = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("json") => read_session_file(&path).is_none(),
+                Some("tmp") => entry.metadata().and_then(|metadata| metadata.modified())
+                    .map(|modified| modified.elapsed().unwrap_or_default() > STALE_TEMP_AGE)
+                    .unwrap_or(false),
+                _ => false
+            };
+            if stale {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, id: &str) -> SessionResult<PathBuf> {
+        if !is_valid_id(id) {
+            return Err(SessionErrors::InvalidId { id: String::from(id) });
+        }
+        Ok(self.directory.join(format!("{}.json", id)))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> SessionResult<Option<SessionData>> {
+        Ok(read_session_file(&self.path(id)?))
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> SessionResult<()> {
+        let path = self.path(id)?;
+        let expires = unix_seconds(SystemTime::now() + ttl);
+        let contents = json!({ "expires": expires, "data": data }).to_string();
+
+        let temp_path = path.with_extension(format!("json.{}.tmp", random_token(TEMP_SUFFIX_BYTES)));
+        if let Err(source) = write_private(&temp_path, contents.as_bytes()).and_then(|_| fs::rename(&temp_path, &path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(SessionErrors::Store { source });
+        }
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> SessionResult<()> {
+        match fs::remove_file(self.path(id)?) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(source) => Err(SessionErrors::Store { source })
+        }
+    }
+}
+
+fn read_session_file(path: &Path) -> Option<SessionData> {
+    let contents = fs::read_to_string(path).ok()?;
+    let mut stored: Value = serde_json::from_str(&contents).ok()?;
+    if stored["expires"].as_u64()? <= unix_seconds(SystemTime::now()) {
+        return None;
+    }
+    match stored["data"].take() {
+        Value::Object(data) => Some(data),
+        _ => None
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+fn generate_id() -> String {
+    random_token(SESSION_ID_BYTES)
+}
+
+fn random_token(size: usize) -> String {
+    let mut bytes = vec![0u8; size];
+    getrandom::getrandom(&mut bytes).expect("the operating system random number generator is unavailable");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http_enums::ResponseStatusCode;
+    use crate::router::Router;
+    use crate::server::test_support::exchange;
+    use std::thread;
+
+    fn roundtrip(router: &Router, raw_request: &str) -> String {
+        exchange(|response| router.dispatch(Request::from_str(raw_request).unwrap(), response).unwrap())
+    }
+
+    fn session_cookie(received: &str) -> Option<String> {
+        received.lines()
+            .find_map(|line| line.strip_prefix("Set-Cookie: session_id="))
+            .map(|cookie| String::from(cookie.split(';').next().unwrap()))
+    }
+
+    fn counter_router<S: SessionStore>(store: S) -> Router {
+        let mut router = Router::new();
+        router.middleware(SessionMiddleware::new(store));
+        router.get("/count", |req, res| {
+            let session = req.session().unwrap();
+            let count: u32 = session.get("count").unwrap_or(0) + 1;
+            session.insert("count", &count)?;
+            res.json(ResponseStatusCode::OK, &count)?;
+            Ok(())
+        });
+        router.get("/peek", |req, res| {
+            let count: Option<u32> = req.session().unwrap().get("count");
+            res.json(ResponseStatusCode::OK, &count)?;
+            Ok(())
+        });
+        router.get("/logout", |req, res| {
+            req.session().unwrap().destroy();
+            res.send(ResponseStatusCode::NO_CONTENT)?;
+            Ok(())
+        });
+        router
+    }
+
+    #[test]
+    fn sessions_persist_between_requests() {
+        let router = counter_router(MemoryStore::default());
+
+        let received = roundtrip(&router, "GET /peek HTTP/1.1\r\n\r\n");
+        assert!(received.ends_with("null"));
+        assert_eq!(session_cookie(&received), None);
+
+        let received = roundtrip(&router, "GET /count HTTP/1.1\r\n\r\n");
+        assert!(received.ends_with("1"));
+        assert!(received.contains("; Path=/; Max-Age=86400; HttpOnly; SameSite=Lax"));
+        let id = session_cookie(&received).unwrap();
+
+        let request = format!("GET /count HTTP/1.1\r\nCookie: session_id={}\r\n\r\n", id);
+        let received = roundtrip(&router, &request);
+        assert!(received.ends_with("2"));
+        assert_eq!(session_cookie(&received), None);
+
+        let request = format!("GET /logout HTTP/1.1\r\nCookie: session_id={}\r\n\r\n", id);
+        let received = roundtrip(&router, &request);
+        assert!(received.contains("Set-Cookie: session_id=; Path=/; Max-Age=0"));
+
+        let request = format!("GET /peek HTTP/1.1\r\nCookie: session_id={}\r\n\r\n", id);
+        assert!(roundtrip(&router, &request).ends_with("null"));
+    }
+
+    #[test]
+    fn memory_store_expires_sessions() {
+        let store = MemoryStore::new(Duration::from_millis(0));
+        let mut data = SessionData::new();
+        data.insert(String::from("user"), json!(42));
+
+        store.save("short", &data, Duration::from_millis(10)).unwrap();
+        store.save("long", &data, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load("short").unwrap(), Some(data.clone()));
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.load("short").unwrap(), None);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.load("long").unwrap(), Some(data));
+    }
+
+    #[test]
+    fn file_store_round_trip() {
+        let directory = std::env::temp_dir().join(format!("rusttp-sessions-{}", std::process::id()));
+        let store = FileStore::new(&directory).unwrap();
+        let mut data = SessionData::new();
+        data.insert(String::from("cart"), json!(["apple", "pear"]));
+
+        store.save("abc_123", &data, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load("abc_123").unwrap(), Some(data.clone()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(directory.join("abc_123.json")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(store.load("missing").unwrap(), None);
+        assert!(store.load("../escape").is_err());
+
+        store.save("expired", &data, Duration::from_secs(0)).unwrap();
+        assert_eq!(store.load("expired").unwrap(), None);
+        assert!(directory.join("expired.json").exists());
+
+        let orphan = directory.join("abc_123.json.crashed.tmp");
+        fs::File::create(&orphan).unwrap().set_modified(SystemTime::now() - Duration::from_secs(3600)).unwrap();
+        let in_flight = directory.join("abc_123.json.writing.tmp");
+        fs::File::create(&in_flight).unwrap();
+
+        store.sweep().unwrap();
+        assert!(!directory.join("expired.json").exists());
+        assert!(!orphan.exists());
+        assert!(in_flight.exists());
+        assert!(directory.join("abc_123.json").exists());
+
+        store.remove("abc_123").unwrap();
+        assert_eq!(store.load("abc_123").unwrap(), None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn file_store_concurrent_saves() {
+        let directory = std::env::temp_dir().join(format!("rusttp-sessions-concurrent-{}", std::process::id()));
+        let store = Arc::new(FileStore::new(&directory).unwrap());
+
+        let writers: Vec<thread::JoinHandle<()>> = (0..8).map(|writer| {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                let mut data = SessionData::new();
+                data.insert(String::from("writer"), json!(writer));
+                data.insert(String::from("padding"), json!("x".repeat(64 * 1024)));
+                for _ in 0..10 {
+                    store.save("shared", &data, Duration::from_secs(60)).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let data = store.load("shared").unwrap().unwrap();
+        assert!(data["writer"].as_u64().unwrap() < 8);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn signed_session_cookies() {
+        let key = CookieKey::new(b"session secret");
+        let mut router = Router::new();
+        router.middleware(SessionMiddleware::new(MemoryStore::default()).signed(key.clone()));
+        router.get("/login", |req, res| {
+            req.session().unwrap().insert("user", &"ada")?;
+            res.send(ResponseStatusCode::OK)?;
+            Ok(())
+        });
+        router.get("/me", |req, res| {
+            let user: Option<String> = req.session().unwrap().get("user");
+            res.json(ResponseStatusCode::OK, &user)?;
+            Ok(())
+        });
+
+        let signed = session_cookie(&roundtrip(&router, "GET /login HTTP/1.1\r\n\r\n")).unwrap();
+        let id = key.verify("session_id", &signed).unwrap();
+
+        let request = format!("GET /me HTTP/1.1\r\nCookie: session_id={}\r\n\r\n", signed);
+        assert!(roundtrip(&router, &request).ends_with("\"ada\""));
+
+        let request = format!("GET /me HTTP/1.1\r\nCookie: session_id={}\r\n\r\n", id);
+        assert!(roundtrip(&router, &request).ends_with("null"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use crate::connection::{Connection, ListenAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+struct TrackedConnection {
+    stream: Connection,
+    busy: bool,
+}
+
+struct ShutdownState {
+    shutting_down: AtomicBool,
+    next_id: AtomicUsize,
+    connections: Mutex<HashMap<usize, TrackedConnection>>,
+    drained: Condvar,
+    wake_addresses: Mutex<Vec<ListenAddr>>,
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> ShutdownHandle {
+        ShutdownHandle {
+            state: Arc::new(ShutdownState {
+                shutting_down: AtomicBool::new(false),
+                next_id: AtomicUsize::new(0),
+                connections: Mutex::new(HashMap::new()),
+                drained: Condvar::new(),
+                wake_addresses: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    pub fn shutdown(&self) {
+        if self.state.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        for connection in self.state.connections.lock().unwrap().values() {
+            if !connection.busy {
+                let _ = connection.stream.shutdown(Shutdown::Read);
+            }
+        }
+
+        for address in self.state.wake_addresses.lock().unwrap().iter() {
+            address.wake();
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn add_wake_address(&self, address: ListenAddr) {
+        let address = match address {
+            ListenAddr::Tcp(address) => {
+                let ip = match address.ip() {
+                    IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    ip => ip
+                };
+                ListenAddr::Tcp(SocketAddr::new(ip, address.port()))
+            }
+            address => address
+        };
+        self.state.wake_addresses.lock().unwrap().push(address);
+    }
+
+    pub(crate) fn track(&self, stream: &Connection) -> Option<ConnectionGuard> {
+        let stream = stream.try_clone().ok()?;
+        let mut connections = self.state.connections.lock().unwrap();
+        if self.is_shutting_down() {
+            return None;
+        }
+
+        let id = self.state.next_id.fetch_add(1, Ordering::SeqCst);
+        connections.insert(id, TrackedConnection { stream, busy: false });
+        Some(ConnectionGuard { handle: self.clone(), id })
+    }
+
+    pub(crate) fn wait_for_connections(&self, grace_period: Duration) {
+        let deadline = Instant::now() + grace_period;
+        let mut connections = self.state.connections.lock().unwrap();
+
+        while !connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            connections = self.state.drained.wait_timeout(connections, deadline - now).unwrap().0;
+        }
+
+        for connection in connections.values() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    handle: ShutdownHandle,
+    id: usize,
+}
+
+impl ConnectionGuard {
+    pub(crate) fn set_busy(&self, busy: bool) -> bool {
+        let mut connections = self.handle.state.connections.lock().unwrap();
+        if let Some(connection) = connections.get_mut(&self.id) {
+            connection.busy = busy;
+        }
+        !self.handle.is_shutting_down()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.handle.state.connections.lock().unwrap();
+        connections.remove(&self.id);
+        self.handle.state.drained.notify_all();
+    }
+}
@@ -1,23 +1,95 @@
-use std::net::TcpListener;
-use std::io::prelude::*;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
+use crate::connection::{ListenAddr, Listener};
+#[cfg(unix)]
+use crate::connection::{UnixSocket, UnixSocketOptions};
 use crate::request::Request;
+use crate::request_reader::{RequestLimits, RequestReader, RequestTimeouts};
 use crate::response::Response;
+use crate::server_errors::{HandlerError, HandlerResult, RequestErrors, ServerErrors, ServerResult};
+use crate::shutdown::ShutdownHandle;
+
+use crate::http_enums::ResponseStatusCode;
 
 use threadpool::ThreadPool;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 type ServerJob = Box<dyn FnOnce() + Send + 'static>;
+type ErrorHandler = dyn Fn(&RequestErrors, &mut Response) + Send + Sync + 'static;
+
+pub trait HandlerOutcome {
+    fn into_result(self) -> HandlerResult;
+}
+
+impl HandlerOutcome for () {
+    fn into_result(self) -> HandlerResult {
+        Ok(())
+    }
+}
+
+impl HandlerOutcome for HandlerResult {
+    fn into_result(self) -> HandlerResult {
+        self
+    }
+}
+
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: Request, response: &mut Response) -> HandlerResult;
+
+    fn body_limit_for(&self, _request: &Request) -> Option<usize> {
+        None
+    }
+
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+}
+
+impl<T, R> Handler for T
+    where
+        T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+        R: HandlerOutcome,
+{
+    fn handle(&self, request: Request, response: &mut Response) -> HandlerResult {
+        self(request, response).into_result()
+    }
+}
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct TCPServer {
-    listener: TcpListener,
-    sender: Sender<ServerJob>,
+    listeners: Vec<Listener>,
+    pool: ThreadPool,
+    sender: Mutex<Option<Sender<ServerJob>>>,
+    shutdown: ShutdownHandle,
+    timeouts: RequestTimeouts,
+    shutdown_timeout: Duration,
+    limits: RequestLimits,
+    error_handler: Arc<ErrorHandler>,
 }
 
 impl TCPServer {
-    pub fn new(port: &str, n_workers: usize, n_jobs: usize) -> TCPServer {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap();
+    pub fn new<A: ToSocketAddrs>(address: A, n_workers: usize, n_jobs: usize) -> ServerResult<TCPServer> {
+        TCPServer::with_listeners(bind_all(address)?, n_workers, n_jobs)
+    }
 
+    #[cfg(unix)]
+    pub fn new_unix<P: AsRef<Path>>(path: P, options: UnixSocketOptions, n_workers: usize, n_jobs: usize) -> ServerResult<TCPServer> {
+        match UnixSocket::bind(path, &options) {
+            Ok(socket) => TCPServer::with_listeners(vec![Listener::Unix(socket)], n_workers, n_jobs),
+            Err(source) => Err(ServerErrors::FailedToStart { source })
+        }
+    }
+
+    fn with_listeners(listeners: Vec<Listener>, n_workers: usize, n_jobs: usize) -> ServerResult<TCPServer> {
         let pool = ThreadPool::new(n_workers);
 
         let (tx, rx): (Sender<ServerJob>, Receiver<ServerJob>) = channel();
@@ -27,35 +99,240 @@ impl TCPServer {
         for _ in 0..n_jobs {
             let rx = Arc::clone(&receiver);
             pool.execute(move || loop {
-                let job = rx.lock().unwrap().recv().unwrap();
+                let job = match rx.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break
+                };
 
                 println!("Worker got a job; executing.");
 
-                job();
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                    eprintln!("Worker job panicked: {}", panic_message(payload.as_ref()));
+                }
             });
         }
 
-        TCPServer { listener, sender: tx }
+        let mut server = TCPServer {
+            listeners: Vec::new(),
+            pool,
+            sender: Mutex::new(Some(tx)),
+            shutdown: ShutdownHandle::new(),
+            timeouts: RequestTimeouts::default(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            limits: RequestLimits::default(),
+            error_handler: Arc::new(send_request_error),
+        };
+
+        for listener in listeners {
+            server.add_listener(listener)?;
+        }
+
+        Ok(server)
+    }
+
+    pub fn bind<A: ToSocketAddrs>(&mut self, address: A) -> ServerResult<()> {
+        for listener in bind_all(address)? {
+            self.add_listener(listener)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(&mut self, path: P, options: UnixSocketOptions) -> ServerResult<()> {
+        match UnixSocket::bind(path, &options) {
+            Ok(socket) => self.add_listener(Listener::Unix(socket)),
+            Err(source) => Err(ServerErrors::FailedToStart { source })
+        }
+    }
+
+    fn add_listener(&mut self, listener: Listener) -> ServerResult<()> {
+        match listener.listen_addr() {
+            Ok(address) => self.shutdown.add_wake_address(address),
+            Err(source) => return Err(ServerErrors::FailedToStart { source })
+        }
+        self.listeners.push(listener);
+
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self.local_addrs()?.first() {
+            Some(address) => Ok(*address),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "server has no TCP listener"))
+        }
     }
 
-    pub fn listen<T>(&self, listener: T)
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        let mut addresses = Vec::new();
+        for listener in &self.listeners {
+            if let ListenAddr::Tcp(address) = listener.listen_addr()? {
+                addresses.push(address);
+            }
+        }
+
+        Ok(addresses)
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    pub fn set_keep_alive_timeout(&mut self, timeout: Duration) {
+        self.timeouts.keep_alive = timeout;
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: RequestTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    pub fn on_request_error<F>(&mut self, error_handler: F)
+        where
+            F: Fn(&RequestErrors, &mut Response) + Send + Sync + 'static,
+    {
+        self.error_handler = Arc::new(error_handler);
+    }
+
+    pub fn listen<T, R>(&self, listener: T)
         where
-            T: FnOnce(Request, Response) + Copy + Send + Sync + 'static,
+            T: Fn(Request, &mut Response) -> R + Send + Sync + 'static,
+            R: HandlerOutcome,
     {
-        for stream in self.listener.incoming() {
-            let mut stream = stream.unwrap();
+        self.serve(listener);
+    }
+
+    pub fn serve<H: Handler>(&self, handler: H) {
+        let handler = Arc::new(handler);
+
+        thread::scope(|scope| {
+            for listener in &self.listeners {
+                let handler = &handler;
+                scope.spawn(move || self.accept(listener, handler));
+            }
+        });
+
+        self.shutdown.wait_for_connections(self.shutdown_timeout);
+        self.sender.lock().unwrap().take();
+        self.pool.join();
+    }
+
+    pub fn spawn<H: Handler>(self, handler: H) -> ServerResult<ServerHandle> {
+        let addresses = match self.local_addrs() {
+            Ok(addresses) => addresses,
+            Err(source) => return Err(ServerErrors::FailedToStart { source })
+        };
+        let shutdown = self.shutdown_handle();
+
+        let thread = match thread::Builder::new().name(String::from("rusttp-server")).spawn(move || self.serve(handler)) {
+            Ok(thread) => thread,
+            Err(source) => return Err(ServerErrors::FailedToStart { source })
+        };
+
+        Ok(ServerHandle { addresses, shutdown, thread })
+    }
+
+    fn accept<H: Handler>(&self, listener: &Listener, handler: &Arc<H>) {
+        loop {
+            let stream = listener.accept();
+            if self.shutdown.is_shutting_down() {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue
+            };
+            let handler = Arc::clone(handler);
+            let shutdown = self.shutdown.clone();
+            let timeouts = self.timeouts;
+            let limits = self.limits;
+            let error_handler = Arc::clone(&self.error_handler);
 
             self.execute(move || {
-                let mut buffer = [0; 1024];
-                stream.read(&mut buffer).unwrap();
-                let raw_request = String::from_utf8_lossy(&buffer[..]);
-                let raw_request_split: Vec<&str> = raw_request.split("\u{0}").collect();
-                let content = raw_request_split[0];
+                let connection = match shutdown.track(&stream) {
+                    Some(connection) => connection,
+                    None => return
+                };
+
+                if stream.set_write_timeout(Some(timeouts.write)).is_err() {
+                    return;
+                }
 
+                let peer_addr = stream.peer_addr();
+                let mut reader = RequestReader::with_limits(&stream, limits);
+                reader.set_timeouts(timeouts);
 
-                let response = Response::new(stream);
-                let request = Request::from_str(content).unwrap();
-                listener(request, response);
+                loop {
+                    let body_source = |request: &Request| {
+                        if handler.streams_body(request) { stream.try_clone().ok() } else { None }
+                    };
+                    let request = match reader.read_request_streaming(|request| handler.body_limit_for(request), body_source) {
+                        Ok(Some(mut request)) => {
+                            request.set_peer_addr(peer_addr.clone());
+                            request
+                        }
+                        Ok(None) => break,
+                        Err(err) => {
+                            if let Ok(writer) = stream.try_clone() {
+                                let mut response = Response::new(writer);
+                                error_handler(&err, &mut response);
+                                if !response.is_sent() {
+                                    send_request_error(&err, &mut response);
+                                }
+                            }
+                            break;
+                        }
+                    };
+
+                    connection.set_busy(true);
+                    let unread_body = request.unread_body();
+                    let writer = match stream.try_clone() {
+                        Ok(writer) => writer,
+                        Err(_) => break
+                    };
+                    let mut response = Response::new(writer);
+                    response.set_version(request.version());
+                    response.set_method(*request.method());
+                    response.set_keep_alive(request.keep_alive());
+                    let draining = shutdown.clone();
+                    response.on_send(move |outgoing| {
+                        if draining.is_shutting_down() {
+                            outgoing.headers.insert("Connection", "close");
+                        }
+                    });
+                    match panic::catch_unwind(AssertUnwindSafe(|| handler.handle(request, &mut response))) {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => {
+                            if !response.is_sent() {
+                                let _ = response.send_error(&err);
+                            }
+                        }
+                        Err(payload) => {
+                            eprintln!("Handler panicked: {}", panic_message(payload.as_ref()));
+                            response.set_keep_alive(false);
+                            if !response.is_sent() {
+                                let _ = response.send_error(&HandlerError::new(ResponseStatusCode::INTERNAL_SERVER_ERROR, "Internal server error"));
+                            }
+                            break;
+                        }
+                    }
+
+                    if !response.is_sent() || !response.keep_alive() {
+                        break;
+                    }
+                    let unread = unread_body.map_or(0, |unread| unread.load(Ordering::SeqCst));
+                    if (unread > 0 && reader.discard_body(unread).is_err()) || !connection.set_busy(false) {
+                        break;
+                    }
+                }
             });
         }
     }
@@ -66,6 +343,343 @@ impl TCPServer {
     {
         let job = Box::new(f);
 
-        self.sender.send(job).unwrap();
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            let _ = sender.send(job);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+fn send_request_error(err: &RequestErrors, response: &mut Response) {
+    let _ = response.send_error(&HandlerError::from(err));
+}
+
+pub struct ServerHandle {
+    addresses: Vec<SocketAddr>,
+    shutdown: ShutdownHandle,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addresses.first().copied()
+    }
+
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn shutdown(self) {
+        self.shutdown.shutdown();
+        self.join();
+    }
+
+    pub fn join(self) {
+        if let Err(panic) = self.thread.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+fn bind_all<A: ToSocketAddrs>(address: A) -> ServerResult<Vec<Listener>> {
+    let addresses: Vec<SocketAddr> = match address.to_socket_addrs() {
+        Ok(addresses) => addresses.collect(),
+        Err(source) => return Err(ServerErrors::FailedToStart { source })
+    };
+
+    if addresses.is_empty() {
+        let source = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind");
+        return Err(ServerErrors::FailedToStart { source });
+    }
+
+    let mut listeners = Vec::new();
+    for address in addresses {
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(source) => return Err(ServerErrors::FailedToStart { source })
+        };
+        listeners.push(Listener::Tcp(listener));
+    }
+
+    Ok(listeners)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multipart::{MultipartLimits, PartData};
+    use crate::router::Router;
+    use crate::server::test_support::send_raw;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    #[test]
+    fn graceful_shutdown() {
+        let server = TCPServer::new("127.0.0.1:0", 4, 4).unwrap();
+        let address = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+
+        let serving = thread::spawn(move || {
+            server.listen(|req, res| {
+                if req.path() == "/slow" {
+                    thread::sleep(Duration::from_millis(300));
+                }
+                res.send(ResponseStatusCode::OK)?;
+                Ok(())
+            });
+        });
+
+        let mut idle = TcpStream::connect(address).unwrap();
+        idle.write_all(b"GET /fast HTTP/1.1\r\n\r\n").unwrap();
+        let mut buffer = [0; 1024];
+        let size = idle.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..size]).starts_with("HTTP/1.1 200 OK"));
+
+        let mut busy = TcpStream::connect(address).unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        shutdown.shutdown();
+        serving.join().unwrap();
+
+        let mut received = String::new();
+        busy.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+        assert!(received.contains("Connection: close"));
+
+        assert_eq!(idle.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn multiple_listeners() {
+        let mut server = TCPServer::new("127.0.0.1:0", 2, 2).unwrap();
+        server.bind(("127.0.0.1", 0)).unwrap();
+        let addresses: Vec<SocketAddr> = server.local_addrs().unwrap();
+        let shutdown = server.shutdown_handle();
+
+        let serving = thread::spawn(move || {
+            server.listen(|_req, res| {
+                res.send(ResponseStatusCode::OK)?;
+                Ok(())
+            });
+        });
+
+        for address in &addresses {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            assert!(received.starts_with("HTTP/1.1 200 OK"));
+        }
+
+        shutdown.shutdown();
+        serving.join().unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bind_failure() {
+        let server = TCPServer::new("127.0.0.1:0", 1, 1).unwrap();
+        let address = server.local_addr().unwrap();
+
+        match TCPServer::new(address, 1, 1) {
+            Err(ServerErrors::FailedToStart { source }) => assert_eq!(source.kind(), io::ErrorKind::AddrInUse),
+            _ => panic!("expected FailedToStart")
+        }
+        assert!(TCPServer::new("not an address", 1, 1).is_err());
+    }
+
+    #[test]
+    fn spawn_on_ephemeral_port() {
+        let server = TCPServer::new("127.0.0.1:0", 2, 2).unwrap();
+        let address = server.local_addr().unwrap();
+        assert_ne!(address.port(), 0);
+
+        let mut router = Router::new();
+        router.get("/", |_req, res| {
+            res.send(ResponseStatusCode::OK)?;
+            Ok(())
+        });
+        let handle = server.spawn(router).unwrap();
+        assert_eq!(handle.local_addr(), Some(address));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+
+        handle.shutdown();
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serve_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("rusttp-serve-{}.sock", std::process::id()));
+        let server = TCPServer::new_unix(&path, UnixSocketOptions::default(), 2, 2).unwrap();
+        assert!(server.local_addr().is_err());
+
+        let mut router = Router::new();
+        router.get("/peer", |req, res| {
+            res.send_json(ResponseStatusCode::OK, Some(serde_json::json!({ "peer": req.peer_addr().to_string() })))?;
+            Ok(())
+        });
+        let handle = server.spawn(router).unwrap();
+        assert_eq!(handle.local_addr(), None);
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"GET /peer HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+        assert!(received.ends_with("{\"peer\":\"unix:unnamed\"}"));
+
+        handle.shutdown();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn request_errors_are_written_back() {
+        let mut server = TCPServer::new("127.0.0.1:0", 2, 2).unwrap();
+        server.on_request_error(|err, res| {
+            if let RequestErrors::HTTPRequest { .. } = err {
+                let _ = res.send_json(err.status_code(), Some(serde_json::json!({ "custom": err.to_string() })));
+            }
+        });
+        let handle = server.spawn(Router::new()).unwrap();
+        let address = handle.local_addr().unwrap();
+
+        let received = send_raw(address, b"BREW /pot HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 501 NOT IMPLEMENTED"));
+        assert!(received.ends_with("{\"custom\":\"Invalid method: BREW\"}"));
+
+        let received = send_raw(address, b"GET / HTTP/1.1\r\nbroken header\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 400 BAD REQUEST"));
+        assert!(received.contains("Connection: close"));
+        assert!(received.ends_with("{\"error\":\"Invalid request header: broken header\"}"));
+
+        let received = send_raw(address, b"GET / HTTP/3\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 505 HTTP VERSION NOT SUPPORTED"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn handler_panics_return_500() {
+        let mut router = Router::new();
+        router.get("/panic", |_req, _res| -> HandlerResult {
+            panic!("boom");
+        });
+        router.get("/", |_req, res| {
+            res.send(ResponseStatusCode::OK)?;
+            Ok(())
+        });
+
+        let server = TCPServer::new("127.0.0.1:0", 1, 1).unwrap();
+        let handle = server.spawn(router).unwrap();
+        let address = handle.local_addr().unwrap();
+
+        for _ in 0..3 {
+            let received = send_raw(address, b"GET /panic HTTP/1.1\r\n\r\n");
+            assert!(received.starts_with("HTTP/1.1 500 INTERNAL SERVER ERROR"));
+            assert!(received.contains("Connection: close"));
+        }
+
+        let received = send_raw(address, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn stalled_requests_time_out() {
+        let mut server = TCPServer::new("127.0.0.1:0", 2, 2).unwrap();
+        server.set_timeouts(RequestTimeouts {
+            keep_alive: Duration::from_millis(200),
+            header: Duration::from_millis(300),
+            body: Duration::from_millis(200),
+            write: Duration::from_secs(1),
+        });
+        let handle = server.spawn(Router::new()).unwrap();
+        let address = handle.local_addr().unwrap();
+
+        let mut idle = TcpStream::connect(address).unwrap();
+        let mut received = String::new();
+        idle.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "");
+
+        let mut slowloris = TcpStream::connect(address).unwrap();
+        slowloris.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(50));
+            slowloris.write_all(b"X").unwrap();
+        }
+        let mut received = String::new();
+        slowloris.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 408 REQUEST TIMEOUT"));
+        assert!(received.contains("Connection: close"));
+
+        let mut stalled = TcpStream::connect(address).unwrap();
+        stalled.write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}").unwrap();
+        let mut received = String::new();
+        stalled.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 408 REQUEST TIMEOUT"));
+        assert!(received.ends_with("{\"error\":\"Timed out reading the request body\"}"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn streamed_multipart_reads_from_the_connection() {
+        let mut router = Router::new();
+        router.post("/upload", |req: Request, res: &mut Response| {
+            let limits = MultipartLimits { spill_threshold: 1024, max_part_size: 4 * 1024 * 1024, max_total_size: 4 * 1024 * 1024, ..MultipartLimits::default() };
+            let multipart = req.multipart_with_limits(limits)?;
+            let report = multipart.file("report").unwrap();
+            assert!(matches!(report.data(), PartData::File(_)));
+            Ok(res.json(ResponseStatusCode::OK, &report.size())?)
+        }).body_limit(4 * 1024 * 1024).stream_body();
+        router.post("/ignore", |_req, res: &mut Response| Ok(res.send(ResponseStatusCode::NO_CONTENT)?))
+            .stream_body();
+        router.get("/", |_req, res: &mut Response| Ok(res.send(ResponseStatusCode::OK)?));
+        let handle = TCPServer::new("127.0.0.1:0", 2, 2).unwrap().spawn(router).unwrap();
+        let address = handle.local_addr().unwrap();
+
+        let upload = vec![b'x'; 2 * 1024 * 1024];
+        let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"report\"; filename=\"r.bin\"\r\n\r\n".to_vec();
+        body.extend_from_slice(&upload);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        let mut raw = format!("POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        raw.extend_from_slice(&body);
+        raw.extend_from_slice(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let received = send_raw(address, &raw);
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+        assert!(received.contains(&format!("\r\n\r\n{}HTTP/1.1 200 OK", upload.len())));
+
+        let ignored = &upload[..512 * 1024];
+        let mut raw = format!("POST /ignore HTTP/1.1\r\nContent-Length: {}\r\n\r\n", ignored.len()).into_bytes();
+        raw.extend_from_slice(ignored);
+        raw.extend_from_slice(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let received = send_raw(address, &raw);
+        assert!(received.starts_with("HTTP/1.1 204 NO CONTENT"));
+        assert!(received.contains("\r\n\r\nHTTP/1.1 200 OK"));
+
+        handle.shutdown();
+    }
+}
@@ -0,0 +1,28 @@
+use crate::response::Response;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+pub(crate) fn connected_response() -> (Response, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (Response::new(server), client)
+}
+
+pub(crate) fn exchange<F: FnOnce(&mut Response)>(respond: F) -> String {
+    let (mut response, mut client) = connected_response();
+    respond(&mut response);
+    drop(response);
+
+    let mut received = String::new();
+    client.read_to_string(&mut received).unwrap();
+    received
+}
+
+pub(crate) fn send_raw(address: SocketAddr, raw_request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(raw_request).unwrap();
+    let mut received = String::new();
+    stream.read_to_string(&mut received).unwrap();
+    received
+}